@@ -0,0 +1,104 @@
+use super::ray::Ray;
+use super::vec::{Point3, Vec3};
+
+// An axis-aligned bounding box, stored as its two extreme corners.
+#[derive(Clone, Copy)]
+pub struct Aabb {
+    minimum: Point3,
+    maximum: Point3,
+}
+
+impl Aabb {
+    pub fn new(minimum: Point3, maximum: Point3) -> Aabb {
+        Aabb { minimum, maximum }
+    }
+
+    // The smallest box containing both of the given points, in any order.
+    pub fn from_points(a: Point3, b: Point3) -> Aabb {
+        Aabb {
+            minimum: Point3::new(a.x().min(b.x()), a.y().min(b.y()), a.z().min(b.z())),
+            maximum: Point3::new(a.x().max(b.x()), a.y().max(b.y()), a.z().max(b.z())),
+        }
+    }
+
+    pub fn surrounding(&self, other: &Aabb) -> Aabb {
+        Aabb::new(
+            Point3::new(
+                self.minimum.x().min(other.minimum.x()),
+                self.minimum.y().min(other.minimum.y()),
+                self.minimum.z().min(other.minimum.z()),
+            ),
+            Point3::new(
+                self.maximum.x().max(other.maximum.x()),
+                self.maximum.y().max(other.maximum.y()),
+                self.maximum.z().max(other.maximum.z()),
+            ),
+        )
+    }
+
+    // Grow the box by `delta` on every side. Used for flat primitives, whose boxes would otherwise
+    // have zero thickness along one axis.
+    pub fn padded(&self, delta: f64) -> Aabb {
+        let d = Vec3::new(delta, delta, delta);
+        Aabb::new(self.minimum - d, self.maximum + d)
+    }
+
+    pub fn centroid(&self) -> Point3 {
+        0.5 * (self.minimum + self.maximum)
+    }
+
+    // Where `p` is in the box, from 0 at the minimum corner to 1 at the maximum along each axis.
+    pub fn relative(&self, p: Point3) -> Point3 {
+        let d = self.maximum - self.minimum;
+        let o = p - self.minimum;
+        Point3::new(o.x() / d.x(), o.y() / d.y(), o.z() / d.z())
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let d = self.maximum - self.minimum;
+        2.0 * (d.x() * d.y() + d.y() * d.z() + d.z() * d.x())
+    }
+
+    // Slab test: intersect the ray with the three pairs of planes bounding the box and check the
+    // resulting parameter intervals still overlap.
+    pub fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> bool {
+        self.interval(r, t_min, t_max).is_some()
+    }
+
+    // The part of [t_min, t_max] for which the ray is inside the box, if any.
+    pub fn interval(&self, r: &Ray, mut t_min: f64, mut t_max: f64) -> Option<(f64, f64)> {
+        for a in 0..3 {
+            let inv_d = 1.0 / r.direction()[a];
+            let mut t0 = (self.minimum[a] - r.origin()[a]) * inv_d;
+            let mut t1 = (self.maximum[a] - r.origin()[a]) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+            if t_max < t_min {
+                return None;
+            }
+        }
+        Some((t_min, t_max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rays_through_the_box_hit_it() {
+        let bbox = Aabb::new(Point3::new(-1.0, -1.0, -1.0), Point3::new(1.0, 1.0, 1.0));
+
+        let through = Ray::new(Point3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!(bbox.hit(&through, 0.001, f64::INFINITY));
+
+        let beside = Ray::new(Point3::new(2.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!(!bbox.hit(&beside, 0.001, f64::INFINITY));
+
+        let behind = Ray::new(Point3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, 1.0));
+        assert!(!bbox.hit(&behind, 0.001, f64::INFINITY));
+    }
+}
@@ -0,0 +1,255 @@
+use std::f64::consts::PI;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use rand::Rng;
+use serde::Deserialize;
+
+use super::distribution::Distribution2D;
+use super::hdr::{load_hdr, HdrImage};
+use super::light::{Light, LightSample};
+use super::ray::Ray;
+use super::sky::{Sky, SunLight};
+use super::tonemap::luminance;
+use super::vec::{Color, Point3, Vec3};
+
+// What rays that escape the scene see. Without a `background` block in the preset we fall back to
+// the original white-to-green sky gradient.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BackgroundSettings {
+    Solid {
+        color: Color,
+    },
+    // Blends from `bottom` (looking along -up) to `top` (looking along up).
+    Gradient {
+        bottom: Color,
+        top: Color,
+        up: Option<Vec3>,
+    },
+    // An equirectangular (latitude/longitude) Radiance .hdr image, with +y up. `rotation` turns it
+    // around the y axis, in degrees; `intensity` scales its brightness.
+    Environment {
+        path: String,
+        rotation: Option<f64>,
+        intensity: Option<f64>,
+    },
+    // A physically based daylight sky with the sun in it, which also lights the scene directly.
+    // The sun is `elevation` degrees above the horizon and `azimuth` degrees around from -z
+    // towards +x. `turbidity` is the haze, from 2 (very clear, the default is 3) to about 10.
+    // The sky's own units are kcd/m^2; `intensity` scales them, and by default puts a white
+    // surface in the midday sun at about 1.
+    Sky {
+        elevation: f64,
+        azimuth: Option<f64>,
+        turbidity: Option<f64>,
+        intensity: Option<f64>,
+    },
+}
+
+pub struct EnvironmentMap {
+    image: HdrImage,
+    rotation: f64,
+    intensity: f64,
+    // Over the image's pixels, by how much light comes from each.
+    distribution: Distribution2D,
+}
+
+impl EnvironmentMap {
+    fn new(image: HdrImage, rotation: f64, intensity: f64) -> io::Result<EnvironmentMap> {
+        // Neither the lookups nor the distribution have anything to work with without pixels.
+        if image.width == 0 || image.height == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "an environment map needs at least one pixel",
+            ));
+        }
+        // Rows near the poles cover less of the sphere, so their pixels bring in less light.
+        let mut weights = Vec::with_capacity(image.width * image.height);
+        for y in 0..image.height {
+            let sin_theta = (PI * (y as f64 + 0.5) / image.height as f64).sin();
+            for x in 0..image.width {
+                weights.push(luminance(image.get(x, y)).max(0.0) * sin_theta);
+            }
+        }
+        let distribution = Distribution2D::new(&weights, image.width, image.height);
+        Ok(EnvironmentMap {
+            image,
+            rotation,
+            intensity,
+            distribution,
+        })
+    }
+
+    // Where `direction` is in the image, with u and v both in [0, 1), and the angle theta from +y.
+    fn uv(&self, direction: Vec3) -> (f64, f64, f64) {
+        let d = direction.normalized();
+        let theta = d.y().clamp(-1.0, 1.0).acos();
+        let phi = d.x().atan2(-d.z()) + self.rotation;
+        ((phi / (2.0 * PI) + 0.5).rem_euclid(1.0), theta / PI, theta)
+    }
+
+    // Nearest-neighbour lookup of the pixel seen in `direction`.
+    fn color(&self, direction: Vec3) -> Color {
+        let (u, v, _) = self.uv(direction);
+        let x = ((u * self.image.width as f64) as usize).min(self.image.width - 1);
+        let y = ((v * self.image.height as f64) as usize).min(self.image.height - 1);
+
+        self.intensity * self.image.get(x, y)
+    }
+}
+
+// The environment map as a light, sampled by how bright each pixel is.
+pub struct EnvironmentLight {
+    map: Arc<EnvironmentMap>,
+}
+
+impl Light for EnvironmentLight {
+    fn sample(&self, _p: Point3) -> Option<LightSample> {
+        let mut rng = rand::thread_rng();
+        let ((u, v), pdf) = self.map.distribution.sample(rng.gen(), rng.gen());
+        let theta = PI * v;
+        let phi = 2.0 * PI * (u - 0.5) - self.map.rotation;
+        let direction = Vec3::new(
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            -theta.sin() * phi.cos(),
+        );
+        // From density over the image to density over solid angle.
+        let sin_theta = theta.sin();
+        if pdf <= 0.0 || sin_theta <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            direction,
+            distance: f64::INFINITY,
+            radiance: self.map.color(direction),
+            pdf: pdf / (2.0 * PI * PI * sin_theta),
+        })
+    }
+
+    fn pdf(&self, r: &Ray, t: f64) -> f64 {
+        if t.is_finite() {
+            return 0.0;
+        }
+        let (u, v, theta) = self.map.uv(r.direction());
+        let sin_theta = theta.sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        self.map.distribution.pdf(u, v) / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+pub enum Background {
+    Solid(Color),
+    Gradient { bottom: Color, top: Color, up: Vec3 },
+    Environment(Arc<EnvironmentMap>),
+    Sky(Arc<Sky>),
+}
+
+impl Background {
+    pub fn from_settings(settings: &Option<BackgroundSettings>) -> io::Result<Background> {
+        let background = match settings {
+            None => Background::Gradient {
+                bottom: Color::new(1.0, 1.0, 1.0),
+                top: Color::new(0.6, 0.75, 0.6),
+                up: Vec3::new(0.0, 1.0, 0.0),
+            },
+            Some(BackgroundSettings::Solid { color }) => Background::Solid(*color),
+            Some(BackgroundSettings::Gradient { bottom, top, up }) => Background::Gradient {
+                bottom: *bottom,
+                top: *top,
+                up: up.unwrap_or(Vec3::new(0.0, 1.0, 0.0)).normalized(),
+            },
+            Some(BackgroundSettings::Environment {
+                path,
+                rotation,
+                intensity,
+            }) => Background::Environment(Arc::new(EnvironmentMap::new(
+                load_hdr(Path::new(path))?,
+                rotation.unwrap_or(0.0).to_radians(),
+                intensity.unwrap_or(1.0),
+            )?)),
+            Some(BackgroundSettings::Sky {
+                elevation,
+                azimuth,
+                turbidity,
+                intensity,
+            }) => Background::Sky(Arc::new(Sky::new(
+                *elevation,
+                azimuth.unwrap_or(0.0),
+                turbidity.unwrap_or(3.0),
+                intensity.unwrap_or(0.03),
+            ))),
+        };
+        Ok(background)
+    }
+
+    // The part of the background that can be sampled as a light, if any.
+    pub fn light(&self) -> Option<Box<dyn Light>> {
+        match self {
+            Background::Environment(map) => Some(Box::new(EnvironmentLight { map: map.clone() })),
+            Background::Sky(sky) => Some(Box::new(SunLight::new(sky.clone()))),
+            _ => None,
+        }
+    }
+
+    // The colour seen by a ray that leaves the scene travelling in `direction`.
+    pub fn color(&self, direction: Vec3) -> Color {
+        match self {
+            Background::Solid(color) => *color,
+            Background::Gradient { bottom, top, up } => {
+                let t = 0.5 * (direction.normalized().dot(*up) + 1.0);
+                (1.0 - t) * *bottom + t * *top
+            }
+            Background::Environment(map) => map.color(direction),
+            Background::Sky(sky) => sky.color(direction),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn environment_light_samples_its_bright_pixel() {
+        // A dim 4x2 map with one bright pixel in the upper row.
+        let mut pixels = vec![Color::new(0.1, 0.1, 0.1); 8];
+        pixels[2] = Color::new(100.0, 100.0, 100.0);
+        let image = HdrImage {
+            width: 4,
+            height: 2,
+            pixels,
+        };
+        let light = EnvironmentLight {
+            map: Arc::new(EnvironmentMap::new(image, 0.3, 1.0).unwrap()),
+        };
+
+        let mut bright = 0;
+        for _ in 0..1000 {
+            let sample = light.sample(Point3::new(0.0, 0.0, 0.0)).unwrap();
+            let r = Ray::new(Point3::new(0.0, 0.0, 0.0), sample.direction);
+            let pdf = light.pdf(&r, f64::INFINITY);
+            assert!((sample.pdf - pdf).abs() < 1.0e-6 * pdf);
+            if sample.radiance.x() > 1.0 {
+                bright += 1;
+            }
+        }
+        assert!(bright > 950);
+    }
+
+    #[test]
+    fn empty_environment_maps_are_errors() {
+        for (width, height) in [(0, 2), (4, 0), (0, 0)] {
+            let image = HdrImage {
+                width,
+                height,
+                pixels: Vec::new(),
+            };
+            assert!(EnvironmentMap::new(image, 0.0, 1.0).is_err());
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::sync::Arc;
+
+use super::hit::HitRecord;
+use super::material::{Scatter, ScatterRecord};
+use super::ray::Ray;
+use super::texture::Texture;
+use super::vec::{Color, Vec3};
+
+// The outward-facing shading normal, whichever side was hit.
+fn outward_shading_normal(rec: &HitRecord) -> Vec3 {
+    if rec.front_face {
+        rec.shading_normal
+    } else {
+        -1.0 * rec.shading_normal
+    }
+}
+
+// A copy of `rec` with the (outward-facing) shading normal `n`. The geometric normal stays as it
+// was; a perturbed normal that would face away from the ray's side is ignored.
+fn with_shading_normal(rec: &HitRecord, n: Vec3) -> HitRecord {
+    let mut perturbed = rec.clone();
+    perturbed.set_shading_normal(n.normalized());
+    if perturbed.shading_normal.dot(rec.normal) <= 0.0 {
+        perturbed.shading_normal = rec.shading_normal;
+    }
+    perturbed
+}
+
+// Shades `inner` with normals from a tangent-space normal map: red, green and blue in [0, 1] are
+// the normal along dp/du, dp/dv and the surface normal, each scaled to [-1, 1].
+pub struct NormalMap {
+    inner: Arc<dyn Scatter>,
+    map: Arc<dyn Texture>,
+    // Scales the tilt away from the surface normal; 0 leaves the normal alone.
+    strength: f64,
+}
+
+impl NormalMap {
+    pub fn new(inner: Arc<dyn Scatter>, map: Arc<dyn Texture>, strength: f64) -> NormalMap {
+        NormalMap {
+            inner,
+            map,
+            strength,
+        }
+    }
+
+    fn perturb(&self, rec: &HitRecord) -> HitRecord {
+        let n = outward_shading_normal(rec);
+        // An orthonormal frame with the tangent following dp/du, and the bitangent on the same
+        // side as dp/dv, so mirrored texture coordinates still come out right.
+        let tangent = rec.dpdu - rec.dpdu.dot(n) * n;
+        if tangent.near_zero() {
+            return rec.clone();
+        }
+        let tangent = tangent.normalized();
+        let mut bitangent = n.cross(tangent);
+        if bitangent.dot(rec.dpdv) < 0.0 {
+            bitangent = -1.0 * bitangent;
+        }
+
+        let texel = self.map.value(rec.u, rec.v, rec.p);
+        let x = self.strength * (2.0 * texel.x() - 1.0);
+        let y = self.strength * (2.0 * texel.y() - 1.0);
+        let z = 2.0 * texel.z() - 1.0;
+        with_shading_normal(rec, x * tangent + y * bitangent + z * n)
+    }
+}
+
+impl Scatter for NormalMap {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        self.inner.scatter(r_in, &self.perturb(rec))
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Color {
+        self.inner.eval(r_in, &self.perturb(rec), direction)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+        self.inner.pdf(r_in, &self.perturb(rec), direction)
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        self.inner.emitted(r_in, rec)
+    }
+}
+
+// Shades `inner` as if the surface were displaced along its normal by `scale` times a height
+// texture (its average over red, green and blue), as in PBRT's bump mapping.
+pub struct Bump {
+    inner: Arc<dyn Scatter>,
+    height: Arc<dyn Texture>,
+    scale: f64,
+}
+
+// How far apart in u and v the height is sampled to find its slope.
+const BUMP_DELTA: f64 = 0.0005;
+
+impl Bump {
+    pub fn new(inner: Arc<dyn Scatter>, height: Arc<dyn Texture>, scale: f64) -> Bump {
+        Bump {
+            inner,
+            height,
+            scale,
+        }
+    }
+
+    fn displacement(&self, u: f64, v: f64, rec: &HitRecord, offset: Vec3) -> f64 {
+        let h = self.height.value(u, v, rec.p + offset);
+        self.scale * (h.x() + h.y() + h.z()) / 3.0
+    }
+
+    fn perturb(&self, rec: &HitRecord) -> HitRecord {
+        let n = outward_shading_normal(rec);
+        let d = self.displacement(rec.u, rec.v, rec, Vec3::new(0.0, 0.0, 0.0));
+        let d_u = self.displacement(rec.u + BUMP_DELTA, rec.v, rec, BUMP_DELTA * rec.dpdu);
+        let d_v = self.displacement(rec.u, rec.v + BUMP_DELTA, rec, BUMP_DELTA * rec.dpdv);
+
+        // The displaced surface's tangents, leaving out how the normal itself turns.
+        let dpdu = rec.dpdu + ((d_u - d) / BUMP_DELTA) * n;
+        let dpdv = rec.dpdv + ((d_v - d) / BUMP_DELTA) * n;
+        let bumped = dpdu.cross(dpdv);
+        if bumped.near_zero() {
+            return rec.clone();
+        }
+        // The cross product's direction depends on how u and v are laid out; keep it outward.
+        let bumped = if bumped.dot(n) < 0.0 {
+            -1.0 * bumped
+        } else {
+            bumped
+        };
+        with_shading_normal(rec, bumped)
+    }
+}
+
+impl Scatter for Bump {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        self.inner.scatter(r_in, &self.perturb(rec))
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Color {
+        self.inner.eval(r_in, &self.perturb(rec), direction)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+        self.inner.pdf(r_in, &self.perturb(rec), direction)
+    }
+
+    fn emitted(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        self.inner.emitted(r_in, rec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::texture::SolidColor;
+    use crate::vec::Point3;
+
+    // Rises by 1 over each unit of u.
+    struct Ramp;
+
+    impl Texture for Ramp {
+        fn value(&self, u: f64, _v: f64, _p: Point3) -> Color {
+            Color::new(u, u, u)
+        }
+    }
+
+    fn flat_hit() -> HitRecord {
+        HitRecord {
+            p: Point3::new(0.0, 0.0, 0.0),
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            shading_normal: Vec3::new(0.0, 0.0, 1.0),
+            mat: Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+            t: 1.0,
+            u: 0.5,
+            v: 0.5,
+            dpdu: Vec3::new(2.0, 0.0, 0.0),
+            dpdv: Vec3::new(0.0, 2.0, 0.0),
+            front_face: true,
+        }
+    }
+
+    #[test]
+    fn normal_maps_tilt_only_the_shading_normal() {
+        let inner = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        // Tilted 45 degrees towards +u.
+        let map = Arc::new(SolidColor::new(Color::new(1.0, 0.5, 1.0)));
+        let rec = NormalMap::new(inner.clone(), map, 1.0).perturb(&flat_hit());
+        let expected = Vec3::new(1.0, 0.0, 1.0).normalized();
+        assert!((rec.shading_normal - expected).near_zero());
+        assert!((rec.normal - Vec3::new(0.0, 0.0, 1.0)).near_zero());
+
+        // The flat blue of an untouched normal map changes nothing.
+        let flat = Arc::new(SolidColor::new(Color::new(0.5, 0.5, 1.0)));
+        let rec = NormalMap::new(inner, flat, 1.0).perturb(&flat_hit());
+        assert!((rec.shading_normal - Vec3::new(0.0, 0.0, 1.0)).near_zero());
+    }
+
+    #[test]
+    fn bumps_tilt_the_shading_normal_down_the_slope() {
+        let inner = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        // The height climbs 2 over a unit of u, which spans 2 units of distance: a 45 degree slope
+        // facing back towards -u.
+        let rec = Bump::new(inner.clone(), Arc::new(Ramp), 2.0).perturb(&flat_hit());
+        let expected = Vec3::new(-1.0, 0.0, 1.0).normalized();
+        assert!((rec.shading_normal.normalized() - expected).near_zero());
+        assert!((rec.normal - Vec3::new(0.0, 0.0, 1.0)).near_zero());
+
+        // A constant height is as flat as no bump at all.
+        let flat = Arc::new(SolidColor::new(Color::new(0.7, 0.7, 0.7)));
+        let rec = Bump::new(inner, flat, 2.0).perturb(&flat_hit());
+        assert!((rec.shading_normal.normalized() - Vec3::new(0.0, 0.0, 1.0)).near_zero());
+    }
+}
@@ -0,0 +1,298 @@
+use std::cmp::Ordering;
+
+use super::aabb::Aabb;
+use super::hit::{Hit, HitRecord, World};
+use super::ray::Ray;
+
+// Relative costs used by the surface area heuristic. Only their ratio matters.
+const TRAVERSAL_COST: f64 = 1.0;
+const INTERSECTION_COST: f64 = 2.0;
+// Nodes with more objects than this are always split, even if SAH says a leaf would be cheaper.
+const MAX_LEAF_SIZE: usize = 4;
+
+enum BvhNode {
+    // A run of `count` objects in `Bvh::objects`, starting at `first`.
+    Leaf {
+        bbox: Aabb,
+        first: usize,
+        count: usize,
+    },
+    // The left child always directly follows its parent in `Bvh::nodes`; the right child is at `right`.
+    // `axis` is the axis the children were split along, used to visit the nearer child first.
+    Interior {
+        bbox: Aabb,
+        right: usize,
+        axis: usize,
+    },
+}
+
+// A bounding volume hierarchy over a World, built with the surface area heuristic (SAH).
+// Objects without a bounding box (if there are any) are kept to one side and tested linearly.
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    objects: World,
+    unbounded: World,
+}
+
+impl Bvh {
+    pub fn new(world: World) -> Bvh {
+        let mut bounded = Vec::new();
+        let mut unbounded = World::new();
+        for object in world {
+            match object.bounding_box() {
+                Some(bbox) => bounded.push((bbox, object)),
+                None => unbounded.push(object),
+            }
+        }
+
+        let mut nodes = Vec::new();
+        if !bounded.is_empty() {
+            build(&mut bounded, 0, &mut nodes);
+        }
+
+        Bvh {
+            nodes,
+            objects: bounded.into_iter().map(|(_, object)| object).collect(),
+            unbounded,
+        }
+    }
+
+    fn hit_node(&self, index: usize, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        match self.nodes[index] {
+            BvhNode::Leaf { bbox, first, count } => {
+                if !bbox.hit(r, t_min, t_max) {
+                    return None;
+                }
+                self.objects[first..first + count].iter().fold(
+                    None,
+                    |closest: Option<HitRecord>, object| {
+                        let t = closest.as_ref().map_or(t_max, |rec| rec.t);
+                        object.hit(r, t_min, t).or(closest)
+                    },
+                )
+            }
+            BvhNode::Interior { bbox, right, axis } => {
+                if !bbox.hit(r, t_min, t_max) {
+                    return None;
+                }
+                // Visit the child nearer to the ray origin first, so the far one can often be
+                // culled by the shortened t_max.
+                let (near, far) = if r.direction()[axis] < 0.0 {
+                    (right, index + 1)
+                } else {
+                    (index + 1, right)
+                };
+                let near_rec = self.hit_node(near, r, t_min, t_max);
+                let t = near_rec.as_ref().map_or(t_max, |rec| rec.t);
+                self.hit_node(far, r, t_min, t).or(near_rec)
+            }
+        }
+    }
+
+    fn transmittance_node(&self, index: usize, r: &Ray, t_min: f64, t_max: f64) -> f64 {
+        match self.nodes[index] {
+            BvhNode::Leaf { bbox, first, count } => {
+                if !bbox.hit(r, t_min, t_max) {
+                    return 1.0;
+                }
+                self.objects[first..first + count]
+                    .iter()
+                    .map(|object| object.transmittance(r, t_min, t_max))
+                    .product()
+            }
+            BvhNode::Interior { bbox, right, .. } => {
+                if !bbox.hit(r, t_min, t_max) {
+                    return 1.0;
+                }
+                let left = self.transmittance_node(index + 1, r, t_min, t_max);
+                if left == 0.0 {
+                    return 0.0;
+                }
+                left * self.transmittance_node(right, r, t_min, t_max)
+            }
+        }
+    }
+}
+
+fn node_bbox(node: &BvhNode) -> Aabb {
+    match node {
+        BvhNode::Leaf { bbox, .. } | BvhNode::Interior { bbox, .. } => *bbox,
+    }
+}
+
+fn enclosing(objects: &[(Aabb, Box<dyn Hit>)]) -> Aabb {
+    objects[1..]
+        .iter()
+        .fold(objects[0].0, |acc, (bbox, _)| acc.surrounding(bbox))
+}
+
+fn sort_by_centroid(objects: &mut [(Aabb, Box<dyn Hit>)], axis: usize) {
+    objects.sort_by(|a, b| {
+        a.0.centroid()[axis]
+            .partial_cmp(&b.0.centroid()[axis])
+            .unwrap_or(Ordering::Equal)
+    });
+}
+
+// Returns the SAH cost of splitting `objects` (already sorted along some axis) into `[..i]` and
+// `[i..]`, for the best `i`, together with that `i`.
+fn best_split(objects: &[(Aabb, Box<dyn Hit>)]) -> (f64, usize) {
+    let n = objects.len();
+
+    // right_areas[i] is the surface area of the box around objects[i..].
+    let mut right_areas = vec![0.0; n];
+    let mut right_bbox = objects[n - 1].0;
+    for i in (1..n).rev() {
+        right_bbox = right_bbox.surrounding(&objects[i].0);
+        right_areas[i] = right_bbox.surface_area();
+    }
+
+    let mut best = (f64::INFINITY, n / 2);
+    let mut left_bbox = objects[0].0;
+    for i in 1..n {
+        left_bbox = left_bbox.surrounding(&objects[i - 1].0);
+        let cost = left_bbox.surface_area() * (i as f64) + right_areas[i] * ((n - i) as f64);
+        if cost < best.0 {
+            best = (cost, i);
+        }
+    }
+    best
+}
+
+// Builds the subtree for `objects` into `nodes`, reordering `objects` in place so that every leaf
+// refers to a contiguous run. `offset` is the index of `objects[0]` in the full object list.
+fn build(objects: &mut [(Aabb, Box<dyn Hit>)], offset: usize, nodes: &mut Vec<BvhNode>) {
+    let bbox = enclosing(objects);
+    let n = objects.len();
+    let index = nodes.len();
+
+    if n == 1 {
+        nodes.push(BvhNode::Leaf {
+            bbox,
+            first: offset,
+            count: 1,
+        });
+        return;
+    }
+
+    // Try every axis and keep the cheapest split.
+    let mut best_axis = 0;
+    let mut best_cost = f64::INFINITY;
+    let mut best_index = n / 2;
+    for axis in 0..3 {
+        sort_by_centroid(objects, axis);
+        let (cost, i) = best_split(objects);
+        if cost < best_cost {
+            best_axis = axis;
+            best_cost = cost;
+            best_index = i;
+        }
+    }
+
+    let area = bbox.surface_area();
+    let split_cost = if area > 0.0 {
+        TRAVERSAL_COST + INTERSECTION_COST * best_cost / area
+    } else {
+        f64::INFINITY
+    };
+    let leaf_cost = INTERSECTION_COST * (n as f64);
+
+    if n <= MAX_LEAF_SIZE && leaf_cost <= split_cost {
+        nodes.push(BvhNode::Leaf {
+            bbox,
+            first: offset,
+            count: n,
+        });
+        return;
+    }
+
+    sort_by_centroid(objects, best_axis);
+
+    // Placeholder, patched once we know where the right subtree starts.
+    nodes.push(BvhNode::Leaf {
+        bbox,
+        first: offset,
+        count: 0,
+    });
+    let (left, right) = objects.split_at_mut(best_index);
+    build(left, offset, nodes);
+    let right_index = nodes.len();
+    build(right, offset + best_index, nodes);
+
+    nodes[index] = BvhNode::Interior {
+        bbox,
+        right: right_index,
+        axis: best_axis,
+    };
+}
+
+impl Hit for Bvh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let closest = if self.nodes.is_empty() {
+            None
+        } else {
+            self.hit_node(0, r, t_min, t_max)
+        };
+        let t = closest.as_ref().map_or(t_max, |rec| rec.t);
+        self.unbounded.hit(r, t_min, t).or(closest)
+    }
+
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64) -> f64 {
+        let bounded = if self.nodes.is_empty() {
+            1.0
+        } else {
+            self.transmittance_node(0, r, t_min, t_max)
+        };
+        bounded * self.unbounded.transmittance(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        if !self.unbounded.is_empty() {
+            return None;
+        }
+        self.nodes.first().map(node_bbox)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rand::Rng;
+
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::sphere::Sphere;
+    use crate::vec::{Color, Point3, Vec3};
+
+    fn random_spheres() -> World {
+        let mat = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let mut world = World::new();
+        for _ in 0..200 {
+            let center = Point3::random(-10.0..10.0);
+            let radius = rand::thread_rng().gen_range(0.1..1.0);
+            world.push(Box::new(Sphere::new(center, radius, mat.clone())));
+        }
+        world
+    }
+
+    #[test]
+    fn bvh_hits_match_linear_scan() {
+        let bvh = Bvh::new(random_spheres());
+
+        for _ in 0..1000 {
+            let r = Ray::new(Point3::random(-15.0..15.0), Vec3::random_in_unit_sphere());
+            // Compare against a plain linear scan over the same objects.
+            let expected = bvh
+                .objects
+                .iter()
+                .fold(None, |closest: Option<HitRecord>, object| {
+                    let t = closest.as_ref().map_or(f64::INFINITY, |rec| rec.t);
+                    object.hit(&r, 0.001, t).or(closest)
+                })
+                .map(|rec| rec.t);
+            let actual = bvh.hit(&r, 0.001, f64::INFINITY).map(|rec| rec.t);
+            assert_eq!(expected, actual);
+        }
+    }
+}
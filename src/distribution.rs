@@ -0,0 +1,115 @@
+// A piecewise-constant distribution over [0, 1), with density proportional to `func` (which must
+// not be negative) over each of its equal steps. Sampled by inverting its cumulative distribution,
+// as in PBRT. It needs at least one step.
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    // The integral of `func` over [0, 1).
+    integral: f64,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f64>) -> Distribution1D {
+        let n = func.len();
+        let mut cdf = vec![0.0; n + 1];
+        for i in 0..n {
+            cdf[i + 1] = cdf[i] + func[i] / n as f64;
+        }
+        let integral = cdf[n];
+        for (i, c) in cdf.iter_mut().enumerate() {
+            // With nothing to go by, every step is as likely as any other.
+            *c = if integral > 0.0 {
+                *c / integral
+            } else {
+                i as f64 / n as f64
+            };
+        }
+        Distribution1D {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    // A point for the uniform random number `u`, its density, and the step it's in.
+    pub fn sample(&self, u: f64) -> (f64, f64, usize) {
+        // The last step whose cdf is at most u.
+        let i = (self.cdf.partition_point(|&c| c <= u).max(1) - 1).min(self.func.len() - 1);
+        let width = self.cdf[i + 1] - self.cdf[i];
+        let du = if width > 0.0 {
+            (u - self.cdf[i]) / width
+        } else {
+            0.0
+        };
+        let x = (i as f64 + du) / self.func.len() as f64;
+        (x.min(1.0 - f64::EPSILON), self.pdf(i), i)
+    }
+
+    // The density over step `i`.
+    pub fn pdf(&self, i: usize) -> f64 {
+        if self.integral > 0.0 {
+            self.func[i] / self.integral
+        } else {
+            1.0
+        }
+    }
+}
+
+// A piecewise-constant distribution over [0, 1)^2, on a grid of `width` by `height` cells given
+// row by row: a distribution over the rows, and one over each row. The grid can't be empty.
+pub struct Distribution2D {
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    pub fn new(func: &[f64], width: usize, height: usize) -> Distribution2D {
+        let rows: Vec<Distribution1D> = func
+            .chunks(width)
+            .take(height)
+            .map(|row| Distribution1D::new(row.to_vec()))
+            .collect();
+        let marginal = Distribution1D::new(rows.iter().map(|row| row.integral).collect());
+        Distribution2D { rows, marginal }
+    }
+
+    // A point (u, v) for two uniform random numbers, and its density.
+    pub fn sample(&self, u1: f64, u2: f64) -> ((f64, f64), f64) {
+        let (v, pdf_v, row) = self.marginal.sample(u2);
+        let (u, pdf_u, _) = self.rows[row].sample(u1);
+        ((u, v), pdf_u * pdf_v)
+    }
+
+    pub fn pdf(&self, u: f64, v: f64) -> f64 {
+        let row = ((v * self.rows.len() as f64) as usize).min(self.rows.len() - 1);
+        let columns = self.rows[row].func.len();
+        let column = ((u * columns as f64) as usize).min(columns - 1);
+        self.marginal.pdf(row) * self.rows[row].pdf(column)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_follow_the_function() {
+        // One bright cell, one dim one and two dark ones.
+        let distribution = Distribution2D::new(&[0.0, 3.0, 1.0, 0.0], 2, 2);
+        let n = 1000;
+        let mut counts = [0; 4];
+        for i in 0..n {
+            for j in 0..n {
+                let u1 = (i as f64 + 0.5) / n as f64;
+                let u2 = (j as f64 + 0.5) / n as f64;
+                let ((u, v), pdf) = distribution.sample(u1, u2);
+                assert!((pdf - distribution.pdf(u, v)).abs() < 1.0e-9);
+                counts[2 * (v >= 0.5) as usize + (u >= 0.5) as usize] += 1;
+            }
+        }
+        assert_eq!(counts[0] + counts[3], 0);
+        assert_eq!(counts[1], 3 * counts[2]);
+        // The density over the bright cell, which covers a quarter of the square.
+        assert!((distribution.pdf(0.75, 0.25) - 3.0).abs() < 1.0e-9);
+    }
+}
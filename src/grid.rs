@@ -0,0 +1,196 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::aabb::Aabb;
+use super::vec::Point3;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// A dense grid of densities, one per voxel, with x varying fastest and then y.
+pub struct DensityGrid {
+    resolution: [usize; 3],
+    values: Vec<f64>,
+    // Where the grid is in the scene, if the file says.
+    pub bounds: Option<Aabb>,
+}
+
+impl DensityGrid {
+    pub fn new(resolution: [usize; 3], values: Vec<f64>) -> DensityGrid {
+        assert_eq!(values.len(), resolution.iter().product::<usize>());
+        DensityGrid {
+            resolution,
+            values,
+            bounds: None,
+        }
+    }
+
+    pub fn max(&self) -> f64 {
+        self.values.iter().cloned().fold(0.0, f64::max)
+    }
+
+    fn voxel(&self, x: usize, y: usize, z: usize) -> f64 {
+        self.values[(z * self.resolution[1] + y) * self.resolution[0] + x]
+    }
+
+    // The density at `p`, given in [0, 1] across the grid, interpolated between the voxel
+    // centres. Beyond the outer centres the edge voxels carry on.
+    pub fn density(&self, p: Point3) -> f64 {
+        let mut i = [0; 3];
+        let mut f = [0.0; 3];
+        for a in 0..3 {
+            let n = self.resolution[a];
+            let g = (p[a] * n as f64 - 0.5).clamp(0.0, (n - 1) as f64);
+            i[a] = (g.floor() as usize).min(n.saturating_sub(2));
+            f[a] = g - i[a] as f64;
+        }
+        let next = |a: usize| (i[a] + 1).min(self.resolution[a] - 1);
+
+        let mut density = 0.0;
+        for (dz, z) in [(1.0 - f[2], i[2]), (f[2], next(2))] {
+            for (dy, y) in [(1.0 - f[1], i[1]), (f[1], next(1))] {
+                for (dx, x) in [(1.0 - f[0], i[0]), (f[0], next(0))] {
+                    density += dx * dy * dz * self.voxel(x, y, z);
+                }
+            }
+        }
+        density
+    }
+}
+
+fn read_f32s(bytes: &[u8]) -> Vec<f64> {
+    bytes
+        .chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64)
+        .collect()
+}
+
+fn read_i32(bytes: &[u8], offset: usize) -> i32 {
+    let b = &bytes[offset..offset + 4];
+    i32::from_le_bytes([b[0], b[1], b[2], b[3]])
+}
+
+// Loads a density grid, either
+// - a .vol file, in Mitsuba's dense grid format: "VOL", version 3, the encoding (1 for 32-bit
+//   floats), the resolution in x, y and z, the number of channels (only the first is used) and the
+//   bounds, then the data; or
+// - a .raw file of 32-bit floats and nothing else, whose `resolution` has to be given.
+// Everything is little endian.
+pub fn load_grid(path: &Path, resolution: Option<[usize; 3]>) -> io::Result<DensityGrid> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+    let bytes = fs::read(path)?;
+
+    let grid = match extension.as_deref() {
+        Some("vol") => read_vol(&bytes),
+        Some("raw") => match resolution {
+            Some(resolution) => read_raw(&bytes, resolution),
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a .raw grid needs its `resolution`",
+            )),
+        },
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "don't know how to read this, use a .vol or .raw file",
+        )),
+    };
+    grid.map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+}
+
+// `per_voxel` times the number of voxels, or None if that overflows. The resolution comes from the
+// file or the preset, so it can be anything.
+fn value_count(resolution: [usize; 3], per_voxel: usize) -> Option<usize> {
+    resolution
+        .iter()
+        .try_fold(per_voxel, |count, &n| count.checked_mul(n))
+}
+
+fn read_raw(bytes: &[u8], resolution: [usize; 3]) -> io::Result<DensityGrid> {
+    if resolution.contains(&0) {
+        return Err(invalid(
+            "the resolution must be at least 1 along every axis",
+        ));
+    }
+    let size = value_count(resolution, 4).ok_or_else(|| invalid("bad resolution"))?;
+    if bytes.len() != size {
+        return Err(invalid("the file's size doesn't match the resolution"));
+    }
+    Ok(DensityGrid::new(resolution, read_f32s(bytes)))
+}
+
+fn read_vol(bytes: &[u8]) -> io::Result<DensityGrid> {
+    const HEADER: usize = 48;
+    if bytes.len() < HEADER || &bytes[0..3] != b"VOL" || bytes[3] != 3 {
+        return Err(invalid("not a version 3 VOL file"));
+    }
+    if read_i32(bytes, 4) != 1 {
+        return Err(invalid("only 32-bit float grids are supported"));
+    }
+    let dims = [read_i32(bytes, 8), read_i32(bytes, 12), read_i32(bytes, 16)];
+    let channels = read_i32(bytes, 20);
+    if dims.iter().any(|&d| d < 1) || channels < 1 {
+        return Err(invalid("bad resolution or number of channels"));
+    }
+    let resolution = dims.map(|d| d as usize);
+    let channels = channels as usize;
+    let bounds = read_f32s(&bytes[24..HEADER]);
+
+    let count = value_count(resolution, channels)
+        .ok_or_else(|| invalid("bad resolution or number of channels"))?;
+    let data = read_f32s(&bytes[HEADER..]);
+    if data.len() != count {
+        return Err(invalid("the file's size doesn't match the resolution"));
+    }
+    let mut grid = DensityGrid::new(resolution, data.into_iter().step_by(channels).collect());
+    grid.bounds = Some(Aabb::from_points(
+        Point3::new(bounds[0], bounds[1], bounds[2]),
+        Point3::new(bounds[3], bounds[4], bounds[5]),
+    ));
+    Ok(grid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn densities_are_interpolated_between_voxel_centres() {
+        // Two voxels along x, 0 and 1.
+        let grid = DensityGrid::new([2, 1, 1], vec![0.0, 1.0]);
+        assert_eq!(grid.density(Point3::new(0.25, 0.5, 0.5)), 0.0);
+        assert_eq!(grid.density(Point3::new(0.5, 0.5, 0.5)), 0.5);
+        assert_eq!(grid.density(Point3::new(0.75, 0.5, 0.5)), 1.0);
+        assert_eq!(grid.density(Point3::new(1.0, 0.0, 1.0)), 1.0);
+        assert_eq!(grid.max(), 1.0);
+
+        // A VOL file with the same data, and two channels of which the second is ignored.
+        let mut bytes = b"VOL\x03".to_vec();
+        for i in [1i32, 2, 1, 1, 2] {
+            bytes.extend_from_slice(&i.to_le_bytes());
+        }
+        for f in [-1.0, 0.0, 0.0, 1.0, 1.0, 1.0, 0.0, 9.0, 1.0, 9.0] {
+            bytes.extend_from_slice(&(f as f32).to_le_bytes());
+        }
+        let vol = read_vol(&bytes).unwrap();
+        assert_eq!(vol.density(Point3::new(0.5, 0.5, 0.5)), 0.5);
+        let centre = vol.bounds.unwrap().centroid();
+        assert_eq!((centre.x(), centre.y(), centre.z()), (0.0, 0.5, 0.5));
+    }
+
+    #[test]
+    fn huge_resolutions_are_errors() {
+        let mut bytes = b"VOL\x03".to_vec();
+        for i in [1, i32::MAX, i32::MAX, i32::MAX, 4] {
+            bytes.extend_from_slice(&i.to_le_bytes());
+        }
+        bytes.extend_from_slice(&[0; 24]);
+        assert!(read_vol(&bytes).is_err());
+
+        assert!(read_raw(&[0; 16], [usize::MAX, 2, 2]).is_err());
+    }
+}
@@ -0,0 +1,237 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+use super::vec::Color;
+
+// A floating point image, stored row by row from the top left.
+pub struct HdrImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color>,
+}
+
+impl HdrImage {
+    pub fn get(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+pub fn load_hdr(path: &Path) -> io::Result<HdrImage> {
+    read_hdr(&mut BufReader::new(File::open(path)?))
+}
+
+// Reads a Radiance RGBE (.hdr/.pic) image, either flat or run-length encoded.
+// See https://www.graphics.cornell.edu/~bjw/rgbe.html for the format.
+pub fn read_hdr(reader: &mut impl BufRead) -> io::Result<HdrImage> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if !line.starts_with("#?") {
+        return Err(invalid("not a Radiance HDR file"));
+    }
+
+    // The header is a list of variables, ended by an empty line.
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid("unexpected end of header"));
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(invalid("only the 32-bit_rle_rgbe format is supported"));
+            }
+        }
+    }
+
+    // We only support the standard orientation, "-Y height +X width".
+    line.clear();
+    reader.read_line(&mut line)?;
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let (height, width) = match fields[..] {
+        ["-Y", height, "+X", width] => (
+            height.parse().map_err(|_| invalid("bad image height"))?,
+            width.parse().map_err(|_| invalid("bad image width"))?,
+        ),
+        _ => return Err(invalid("unsupported image orientation")),
+    };
+    // Nothing could look anything up in an image with no pixels.
+    if width == 0 || height == 0 {
+        return Err(invalid("bad image width/height"));
+    }
+
+    let mut pixels = Vec::with_capacity(width * height);
+    let mut scanline = vec![[0u8; 4]; width];
+    for _ in 0..height {
+        read_scanline(reader, &mut scanline)?;
+        pixels.extend(scanline.iter().map(|&rgbe| rgbe_to_color(rgbe)));
+    }
+
+    Ok(HdrImage {
+        width,
+        height,
+        pixels,
+    })
+}
+
+fn rgbe_to_color([r, g, b, e]: [u8; 4]) -> Color {
+    if e == 0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+    let f = 2f64.powi(e as i32 - (128 + 8));
+    Color::new(r as f64 * f, g as f64 * f, b as f64 * f)
+}
+
+// The inverse of rgbe_to_color: a shared exponent for the largest channel, and 8-bit mantissas.
+fn color_to_rgbe(c: Color) -> [u8; 4] {
+    let v = c.x().max(c.y()).max(c.z());
+    if v < 1.0e-32 {
+        return [0, 0, 0, 0];
+    }
+    // v = m * 2^e, with m in [0.5, 1)
+    let mut e = v.log2().floor() as i32 + 1;
+    if v / 2f64.powi(e) >= 1.0 {
+        e += 1;
+    } else if v / 2f64.powi(e) < 0.5 {
+        e -= 1;
+    }
+    let scale = 256.0 / 2f64.powi(e);
+    let mantissa = |x: f64| (x.max(0.0) * scale).min(255.0) as u8;
+    [
+        mantissa(c.x()),
+        mantissa(c.y()),
+        mantissa(c.z()),
+        (e + 128).clamp(0, 255) as u8,
+    ]
+}
+
+// Writes `image` as a flat (not run-length encoded) Radiance RGBE file.
+pub fn write_hdr(output: &mut impl Write, image: &HdrImage) -> io::Result<()> {
+    write!(
+        output,
+        "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+        image.height, image.width
+    )?;
+    for &pixel in &image.pixels {
+        output.write_all(&color_to_rgbe(pixel))?;
+    }
+    Ok(())
+}
+
+fn read_byte(reader: &mut impl Read) -> io::Result<u8> {
+    let mut byte = [0u8; 1];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn read_scanline(reader: &mut impl Read, scanline: &mut [[u8; 4]]) -> io::Result<()> {
+    let width = scanline.len();
+    if !(8..0x8000).contains(&width) {
+        return read_flat_scanline(reader, scanline);
+    }
+
+    let mut first = [0u8; 4];
+    reader.read_exact(&mut first)?;
+    if first[0] != 2 || first[1] != 2 || first[2] & 0x80 != 0 {
+        // Not run-length encoded, so those four bytes were already the first pixel.
+        scanline[0] = first;
+        return read_flat_scanline(reader, &mut scanline[1..]);
+    }
+    if ((first[2] as usize) << 8 | first[3] as usize) != width {
+        return Err(invalid("wrong scanline width"));
+    }
+
+    // Each of the four channels is run-length encoded separately.
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = read_byte(reader)? as usize;
+            if count > 128 {
+                let count = count - 128;
+                if x + count > width {
+                    return Err(invalid("run goes past the end of the scanline"));
+                }
+                let value = read_byte(reader)?;
+                for pixel in &mut scanline[x..x + count] {
+                    pixel[channel] = value;
+                }
+                x += count;
+            } else {
+                if count == 0 || x + count > width {
+                    return Err(invalid("bad run in scanline"));
+                }
+                for pixel in &mut scanline[x..x + count] {
+                    pixel[channel] = read_byte(reader)?;
+                }
+                x += count;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_flat_scanline(reader: &mut impl Read, scanline: &mut [[u8; 4]]) -> io::Result<()> {
+    for pixel in scanline {
+        reader.read_exact(pixel)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_hdr_files_are_read() {
+        let mut file = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 2\n".to_vec();
+        // 0.5 and 2.0 in grey, as (mantissa, exponent) pairs.
+        file.extend([128, 128, 128, 128, 128, 128, 128, 130]);
+
+        let image = read_hdr(&mut &file[..]).unwrap();
+        assert_eq!((image.width, image.height), (2, 1));
+        assert_eq!(image.get(0, 0).x(), 0.5);
+        assert_eq!(image.get(1, 0).z(), 2.0);
+    }
+
+    #[test]
+    fn empty_hdr_files_are_rejected() {
+        for size in ["-Y 0 +X 2", "-Y 1 +X 0", "-Y 0 +X 0"] {
+            let file = format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n{}\n", size);
+            assert!(read_hdr(&mut file.as_bytes()).is_err());
+        }
+    }
+
+    #[test]
+    fn written_hdr_files_read_back() {
+        let image = HdrImage {
+            width: 2,
+            height: 2,
+            pixels: vec![
+                Color::new(0.5, 0.25, 0.0),
+                Color::new(1.0, 2.0, 3.0),
+                Color::new(100.0, 0.0, 0.0),
+                Color::new(0.0, 0.0, 0.0),
+            ],
+        };
+
+        let mut file = Vec::new();
+        write_hdr(&mut file, &image).unwrap();
+        let read = read_hdr(&mut &file[..]).unwrap();
+
+        assert_eq!((read.width, read.height), (2, 2));
+        for (a, b) in image.pixels.iter().zip(&read.pixels) {
+            // RGBE keeps 8 bits of mantissa relative to the brightest channel.
+            let tolerance = a.x().max(a.y()).max(a.z()) / 128.0;
+            for i in 0..3 {
+                assert!((a[i] - b[i]).abs() <= tolerance);
+            }
+        }
+    }
+}
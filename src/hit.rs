@@ -1,29 +1,65 @@
 use std::sync::Arc;
 
+use super::aabb::Aabb;
 use super::material::Scatter;
 use super::ray::Ray;
 use super::vec::{Point3, Vec3};
 
+#[derive(Clone)]
 pub struct HitRecord {
     pub p: Point3,
+    // The geometric normal, on the side the ray came from.
     pub normal: Vec3,
+    // The normal materials shade with, also on the side the ray came from. It is the geometric
+    // normal unless the surface is smoothed (like a mesh with vertex normals) or perturbed.
+    pub shading_normal: Vec3,
     pub mat: Arc<dyn Scatter>,
     pub t: f64,
+    // Surface coordinates of p, for looking up textures.
+    pub u: f64,
+    pub v: f64,
+    // How p moves with u and with v. Neither needs to be normalized, or perpendicular to the
+    // other, but both lie in the surface.
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
     pub front_face: bool,
 }
 
 pub trait Hit: Send + Sync {
     fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+    // None for objects that extend infinitely and so cannot be put in a Bvh.
+    fn bounding_box(&self) -> Option<Aabb>;
+
+    // The fraction of light that gets through this object along `r` between t_min and t_max.
+    // Surfaces block it completely; volumes let some of it through.
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64) -> f64 {
+        if self.hit(r, t_min, t_max).is_some() {
+            0.0
+        } else {
+            1.0
+        }
+    }
 }
 
 impl HitRecord {
-    pub fn set_face_normal(&mut self, r: &Ray, outward_normal: Vec3) -> () {
+    // Sets both normals from the geometric one.
+    pub fn set_face_normal(&mut self, r: &Ray, outward_normal: Vec3) {
         self.front_face = r.direction().dot(outward_normal) < 0.0;
         self.normal = if self.front_face {
             outward_normal
         } else {
             -1.0 * outward_normal
-        }
+        };
+        self.shading_normal = self.normal;
+    }
+
+    // Sets the shading normal from an outward-facing one, flipping it to the side that was hit.
+    pub fn set_shading_normal(&mut self, outward_normal: Vec3) {
+        self.shading_normal = if self.front_face {
+            outward_normal
+        } else {
+            -1.0 * outward_normal
+        };
     }
 }
 
@@ -42,4 +78,27 @@ impl Hit for World {
         }
         tmp_rec
     }
+
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64) -> f64 {
+        let mut transmittance = 1.0;
+        for object in self {
+            transmittance *= object.transmittance(r, t_min, t_max);
+            if transmittance == 0.0 {
+                break;
+            }
+        }
+        transmittance
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let mut output: Option<Aabb> = None;
+        for object in self {
+            let bbox = object.bounding_box()?;
+            output = Some(match output {
+                Some(output) => output.surrounding(&bbox),
+                None => bbox,
+            });
+        }
+        output
+    }
 }
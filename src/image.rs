@@ -0,0 +1,187 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+use super::hdr::{load_hdr, HdrImage};
+use super::vec::Color;
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+// The inverse of the sRGB transfer curve, from an encoded value in [0, 1] to a linear one.
+fn srgb_decode(v: f64) -> f64 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// Loads a .png, .ppm or .hdr image as linear colours. PNG and PPM files are sRGB encoded when they
+// hold colours, as an image editor would have saved them, and not when they hold other data, like
+// normal maps; `srgb` says which.
+pub fn load_image(path: &Path, srgb: bool) -> io::Result<HdrImage> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+
+    let image = match extension.as_deref() {
+        Some("png") => read_png(File::open(path)?, srgb),
+        Some("ppm") => read_ppm(&mut BufReader::new(File::open(path)?), srgb),
+        Some("hdr") => load_hdr(path),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "don't know how to read this, use a .png, .ppm or .hdr file",
+        )),
+    };
+    image.map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+}
+
+// Turns rows of 8- or 16-bit samples, `channels` to a pixel, into linear colours. Grey images
+// have one or two channels, and any alpha channel is ignored.
+fn decode_samples(samples: &[u16], max_value: u16, channels: usize, srgb: bool) -> Vec<Color> {
+    let decode = |sample: u16| {
+        let v = sample as f64 / max_value as f64;
+        if srgb {
+            srgb_decode(v)
+        } else {
+            v
+        }
+    };
+    samples
+        .chunks(channels)
+        .map(|pixel| {
+            if channels < 3 {
+                let grey = decode(pixel[0]);
+                Color::new(grey, grey, grey)
+            } else {
+                Color::new(decode(pixel[0]), decode(pixel[1]), decode(pixel[2]))
+            }
+        })
+        .collect()
+}
+
+fn read_png(input: impl Read, srgb: bool) -> io::Result<HdrImage> {
+    let mut decoder = png::Decoder::new(input);
+    // Palettes and bit depths under 8 are expanded to plain 8-bit samples.
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder
+        .read_info()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader
+        .next_frame(&mut data)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    data.truncate(info.buffer_size());
+
+    let (samples, max_value): (Vec<u16>, u16) = match info.bit_depth {
+        png::BitDepth::Sixteen => (
+            data.chunks(2)
+                .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+                .collect(),
+            u16::MAX,
+        ),
+        _ => (data.iter().map(|&byte| byte as u16).collect(), 255),
+    };
+
+    Ok(HdrImage {
+        width: info.width as usize,
+        height: info.height as usize,
+        pixels: decode_samples(&samples, max_value, info.color_type.samples(), srgb),
+    })
+}
+
+// Reads the next whitespace-separated token of a PPM header, skipping comments.
+fn read_token(reader: &mut impl BufRead) -> io::Result<String> {
+    let mut token = String::new();
+    let mut byte = [0u8; 1];
+    loop {
+        if reader.read(&mut byte)? == 0 {
+            break;
+        }
+        match byte[0] {
+            b'#' if token.is_empty() => {
+                let mut comment = Vec::new();
+                reader.read_until(b'\n', &mut comment)?;
+            }
+            b if b.is_ascii_whitespace() => {
+                if !token.is_empty() {
+                    break;
+                }
+            }
+            b => token.push(b as char),
+        }
+    }
+    if token.is_empty() {
+        return Err(invalid("unexpected end of file"));
+    }
+    Ok(token)
+}
+
+fn read_number(reader: &mut impl BufRead, what: &str) -> io::Result<usize> {
+    read_token(reader)?
+        .parse()
+        .map_err(|_| invalid(&format!("bad {}", what)))
+}
+
+// Reads an ASCII (P3) or binary (P6) PPM image.
+fn read_ppm(reader: &mut impl BufRead, srgb: bool) -> io::Result<HdrImage> {
+    let magic = read_token(reader)?;
+    let width = read_number(reader, "image width")?;
+    let height = read_number(reader, "image height")?;
+    let max_value = read_number(reader, "maximum value")?;
+    if max_value == 0 || max_value > u16::MAX as usize {
+        return Err(invalid("the maximum value needs to be between 1 and 65535"));
+    }
+
+    let count = 3 * width * height;
+    let samples: Vec<u16> = match magic.as_str() {
+        "P3" => (0..count)
+            .map(|_| read_number(reader, "sample").map(|sample| sample as u16))
+            .collect::<io::Result<_>>()?,
+        // Binary samples take two bytes, most significant first, once they don't fit in one.
+        "P6" if max_value > 255 => {
+            let mut data = vec![0u8; 2 * count];
+            reader.read_exact(&mut data)?;
+            data.chunks(2)
+                .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+                .collect()
+        }
+        "P6" => {
+            let mut data = vec![0u8; count];
+            reader.read_exact(&mut data)?;
+            data.into_iter().map(|byte| byte as u16).collect()
+        }
+        _ => return Err(invalid("only P3 and P6 PPM files are supported")),
+    };
+
+    Ok(HdrImage {
+        width,
+        height,
+        pixels: decode_samples(&samples, max_value as u16, 3, srgb),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ascii_and_binary_ppm_files_agree() {
+        let ascii = b"P3\n# a comment\n2 1\n255\n0 255 0\n255 0 188\n".to_vec();
+        let binary = b"P6 2 1 255\n\x00\xff\x00\xff\x00\xbc".to_vec();
+
+        let a = read_ppm(&mut &ascii[..], true).unwrap();
+        let b = read_ppm(&mut &binary[..], true).unwrap();
+        assert_eq!((a.width, a.height), (2, 1));
+        for (a, b) in a.pixels.iter().zip(&b.pixels) {
+            for i in 0..3 {
+                assert_eq!(a[i], b[i]);
+            }
+        }
+        // 188 is about half brightness once the sRGB curve is undone.
+        assert!((a.get(1, 0).z() - 0.5).abs() < 0.01);
+    }
+}
@@ -0,0 +1,402 @@
+use std::f64::consts::PI;
+
+use rand::Rng;
+
+use super::microfacet::Frame;
+use super::ray::Ray;
+use super::vec::{Color, Point3, Vec3};
+
+// A direction towards a light, picked at random.
+pub struct LightSample {
+    // Unit length.
+    pub direction: Vec3,
+    // How far along `direction` the light is.
+    pub distance: f64,
+    pub radiance: Color,
+    // The density, over solid angle, of having picked this direction.
+    pub pdf: f64,
+}
+
+// A light that can be sampled directly, rather than only found by rays that happen to hit it.
+pub trait Light: Send + Sync {
+    // A direction from `p` towards the light, or None if there isn't one to be had from `p`.
+    fn sample(&self, p: Point3) -> Option<LightSample>;
+    // The density with which `sample` from the origin of `r` picks its direction, given that it
+    // ran into something at `t` (infinity if it left the scene), or 0 if that wasn't the light.
+    fn pdf(&self, r: &Ray, t: f64) -> f64;
+}
+
+pub struct Lights {
+    // Lights with an area, one of which is sampled at each hit.
+    pub area: Vec<Box<dyn Light>>,
+    // Points and directions, which rays can never hit by chance. Every one of them is sampled at
+    // each hit; their samples always have a pdf of 1.
+    pub punctual: Vec<Box<dyn Light>>,
+}
+
+impl Lights {
+    pub fn new() -> Lights {
+        Lights {
+            area: Vec::new(),
+            punctual: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.area.is_empty() && self.punctual.is_empty()
+    }
+
+    // Picks one of the area lights uniformly, and samples it.
+    pub fn sample_area(&self, p: Point3) -> Option<LightSample> {
+        if self.area.is_empty() {
+            return None;
+        }
+        let light = &self.area[rand::thread_rng().gen_range(0..self.area.len())];
+        let mut sample = light.sample(p)?;
+        sample.pdf /= self.area.len() as f64;
+        Some(sample)
+    }
+
+    // The density with which `sample_area` picks the direction of `r`, which ran into something
+    // at `t`.
+    pub fn area_pdf(&self, r: &Ray, t: f64) -> f64 {
+        if self.area.is_empty() {
+            return 0.0;
+        }
+        let total: f64 = self.area.iter().map(|light| light.pdf(r, t)).sum();
+        total / self.area.len() as f64
+    }
+}
+
+// Veach's power heuristic: the weight for a sample taken with density `pdf`, when it could also
+// have been taken with density `other_pdf`.
+pub fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (a, b) = (pdf * pdf, other_pdf * other_pdf);
+    if a + b > 0.0 {
+        a / (a + b)
+    } else {
+        0.0
+    }
+}
+
+// A glowing sphere, sampled over the cone of directions it covers.
+pub struct SphereLight {
+    center: Point3,
+    radius: f64,
+    emit: Color,
+}
+
+impl SphereLight {
+    pub fn new(center: Point3, radius: f64, emit: Color) -> SphereLight {
+        SphereLight {
+            center,
+            radius,
+            emit,
+        }
+    }
+
+    // The solid angle of the sphere seen from `p`, or None from inside it.
+    fn solid_angle(&self, p: Point3) -> Option<f64> {
+        let dist2 = (self.center - p).dot(self.center - p);
+        let sin2_max = self.radius * self.radius / dist2;
+        if sin2_max >= 1.0 {
+            return None;
+        }
+        let cos_max = (1.0 - sin2_max).sqrt();
+        // 1 - cos_max, without losing precision for small, far away spheres.
+        Some(2.0 * PI * sin2_max / (1.0 + cos_max))
+    }
+}
+
+impl Light for SphereLight {
+    fn sample(&self, p: Point3) -> Option<LightSample> {
+        let solid_angle = self.solid_angle(p)?;
+        let to_center = self.center - p;
+        let one_minus_cos_max = solid_angle / (2.0 * PI);
+
+        let mut rng = rand::thread_rng();
+        let cos_theta = 1.0 - rng.gen::<f64>() * one_minus_cos_max;
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * rng.gen::<f64>();
+        let direction = Frame::new(to_center.normalized()).to_world(Vec3::new(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            cos_theta,
+        ));
+
+        // The nearer of the points where the direction meets the sphere.
+        let b = direction.dot(-1.0 * to_center);
+        let c = to_center.dot(to_center) - self.radius * self.radius;
+        let distance = -b - (b * b - c).max(0.0).sqrt();
+
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: self.emit,
+            pdf: 1.0 / solid_angle,
+        })
+    }
+
+    fn pdf(&self, r: &Ray, t: f64) -> f64 {
+        if !t.is_finite() {
+            return 0.0;
+        }
+        let point = r.at(t);
+        if ((point - self.center).length() - self.radius).abs() > 1.0e-4 * self.radius {
+            return 0.0;
+        }
+        self.solid_angle(r.origin())
+            .map_or(0.0, |solid_angle| 1.0 / solid_angle)
+    }
+}
+
+// A glowing rectangle in the xy plane, like RectangleXY, lit on both sides and sampled uniformly
+// over its area.
+pub struct RectangleLight {
+    min_point: Point3,
+    max_point: Point3,
+    emit: Color,
+}
+
+impl RectangleLight {
+    pub fn new(min_point: Point3, max_point: Point3, emit: Color) -> RectangleLight {
+        RectangleLight {
+            min_point,
+            max_point,
+            emit,
+        }
+    }
+
+    // Converts the density 1 / area of `point` to one over solid angle, seen from `origin`.
+    fn solid_angle_pdf(&self, origin: Point3, point: Point3) -> Option<(Vec3, f64, f64)> {
+        let area =
+            (self.max_point.x() - self.min_point.x()) * (self.max_point.y() - self.min_point.y());
+        let to_point = point - origin;
+        let distance = to_point.length();
+        let direction = to_point / distance;
+        let cos = direction.z().abs();
+        if area <= 0.0 || cos < 1.0e-8 {
+            return None;
+        }
+        Some((direction, distance, distance * distance / (cos * area)))
+    }
+}
+
+impl Light for RectangleLight {
+    fn sample(&self, p: Point3) -> Option<LightSample> {
+        let mut rng = rand::thread_rng();
+        let point = Point3::new(
+            rng.gen_range(self.min_point.x()..=self.max_point.x()),
+            rng.gen_range(self.min_point.y()..=self.max_point.y()),
+            self.min_point.z(),
+        );
+        let (direction, distance, pdf) = self.solid_angle_pdf(p, point)?;
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: self.emit,
+            pdf,
+        })
+    }
+
+    fn pdf(&self, r: &Ray, t: f64) -> f64 {
+        if !t.is_finite() {
+            return 0.0;
+        }
+        let point = r.at(t);
+        let tolerance = 1.0e-6 * (1.0 + point.z().abs());
+        if (point.z() - self.min_point.z()).abs() > tolerance
+            || point.x() < self.min_point.x()
+            || point.x() > self.max_point.x()
+            || point.y() < self.min_point.y()
+            || point.y() > self.max_point.y()
+        {
+            return 0.0;
+        }
+        self.solid_angle_pdf(r.origin(), point)
+            .map_or(0.0, |(_, _, pdf)| pdf)
+    }
+}
+
+// Light from a single point, falling off with the square of the distance.
+pub struct PointLight {
+    position: Point3,
+    intensity: Color,
+}
+
+impl PointLight {
+    pub fn new(position: Point3, intensity: Color) -> PointLight {
+        PointLight {
+            position,
+            intensity,
+        }
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, p: Point3) -> Option<LightSample> {
+        let to_light = self.position - p;
+        let distance = to_light.length();
+        if distance <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            direction: to_light / distance,
+            distance,
+            radiance: self.intensity / (distance * distance),
+            pdf: 1.0,
+        })
+    }
+
+    fn pdf(&self, _r: &Ray, _t: f64) -> f64 {
+        0.0
+    }
+}
+
+// A point light shining only into a cone around `direction`. It is at full strength within
+// `cos_inner` of the axis, and fades smoothly to nothing at `cos_outer`.
+pub struct SpotLight {
+    point: PointLight,
+    direction: Vec3,
+    cos_inner: f64,
+    cos_outer: f64,
+}
+
+impl SpotLight {
+    // The angles are in degrees, from the axis to the edge of the cone.
+    pub fn new(
+        position: Point3,
+        direction: Vec3,
+        intensity: Color,
+        angle: f64,
+        inner_angle: f64,
+    ) -> SpotLight {
+        let cos_outer = angle.to_radians().cos();
+        SpotLight {
+            point: PointLight::new(position, intensity),
+            direction: direction.normalized(),
+            cos_inner: inner_angle.to_radians().cos().max(cos_outer),
+            cos_outer,
+        }
+    }
+
+    fn falloff(&self, cos: f64) -> f64 {
+        if cos >= self.cos_inner {
+            1.0
+        } else if cos <= self.cos_outer {
+            0.0
+        } else {
+            let t = (cos - self.cos_outer) / (self.cos_inner - self.cos_outer);
+            t * t * (3.0 - 2.0 * t)
+        }
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, p: Point3) -> Option<LightSample> {
+        let mut sample = self.point.sample(p)?;
+        let falloff = self.falloff(self.direction.dot(-1.0 * sample.direction));
+        if falloff <= 0.0 {
+            return None;
+        }
+        sample.radiance = falloff * sample.radiance;
+        Some(sample)
+    }
+
+    fn pdf(&self, _r: &Ray, _t: f64) -> f64 {
+        0.0
+    }
+}
+
+// Parallel light from infinitely far away along `direction`, like the sun. `irradiance` is what
+// it gives a surface facing it.
+pub struct DirectionalLight {
+    direction: Vec3,
+    irradiance: Color,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, irradiance: Color) -> DirectionalLight {
+        DirectionalLight {
+            direction: direction.normalized(),
+            irradiance,
+        }
+    }
+}
+
+impl Light for DirectionalLight {
+    fn sample(&self, _p: Point3) -> Option<LightSample> {
+        Some(LightSample {
+            direction: -1.0 * self.direction,
+            distance: f64::INFINITY,
+            radiance: self.irradiance,
+            pdf: 1.0,
+        })
+    }
+
+    fn pdf(&self, _r: &Ray, _t: f64) -> f64 {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sphere_light_gives_the_right_irradiance() {
+        // Facing a sphere of radiance 1 whose edge is at theta_max, the irradiance is
+        // pi sin^2(theta_max).
+        let light = SphereLight::new(Point3::new(0.0, 0.0, 4.0), 1.0, Color::new(1.0, 1.0, 1.0));
+        let p = Point3::new(0.0, 0.0, 0.0);
+        let n = 20000;
+        let mut irradiance = 0.0;
+        for _ in 0..n {
+            let sample = light.sample(p).unwrap();
+            irradiance += sample.radiance.x() * sample.direction.z() / sample.pdf;
+            // The sampled point is on the sphere, and pdf agrees with sample.
+            let r = Ray::new(p, sample.direction);
+            assert!((light.pdf(&r, sample.distance) - sample.pdf).abs() < 1.0e-9);
+        }
+        let expected = PI / 16.0;
+        assert!((irradiance / n as f64 - expected).abs() < 0.01 * expected);
+    }
+
+    #[test]
+    fn rectangle_light_pdf_agrees_with_its_samples() {
+        let light = RectangleLight::new(
+            Point3::new(-1.0, -1.0, 2.0),
+            Point3::new(1.0, 2.0, 2.0),
+            Color::new(1.0, 1.0, 1.0),
+        );
+        let p = Point3::new(0.3, 0.0, 0.0);
+        for _ in 0..100 {
+            let sample = light.sample(p).unwrap();
+            let r = Ray::new(p, sample.direction);
+            assert!((light.pdf(&r, sample.distance) - sample.pdf).abs() < 1.0e-9 * sample.pdf);
+        }
+        let beside = Ray::new(p, Point3::new(3.0, 0.0, 2.0) - p);
+        assert_eq!(light.pdf(&beside, 1.0), 0.0);
+    }
+
+    #[test]
+    fn spot_lights_fade_out_at_the_edge_of_their_cone() {
+        let white = Color::new(1.0, 1.0, 1.0);
+        let spot = SpotLight::new(
+            Point3::new(0.0, 2.0, 0.0),
+            Vec3::new(0.0, -1.0, 0.0),
+            white,
+            30.0,
+            20.0,
+        );
+        // Straight below, like a point light: 1 / distance^2.
+        let below = spot.sample(Point3::new(0.0, 0.0, 0.0)).unwrap();
+        assert!((below.radiance.x() - 0.25).abs() < 1.0e-9);
+        assert!((below.direction.y() - 1.0).abs() < 1.0e-9);
+        // Between 20 and 30 degrees off the axis it fades, and beyond that there's nothing.
+        let edge = spot.sample(Point3::new(2.0 * 25.0f64.to_radians().tan(), 0.0, 0.0));
+        let edge = edge.unwrap().radiance.x() * (2.0 / 25.0f64.to_radians().cos()).powi(2);
+        assert!(edge > 0.0 && edge < 1.0);
+        assert!(spot.sample(Point3::new(2.0, 0.0, 0.0)).is_none());
+    }
+}
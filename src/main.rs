@@ -1,30 +1,58 @@
+mod aabb;
+mod background;
+mod bump;
+mod bvh;
 mod camera;
+mod distribution;
+mod grid;
+mod hdr;
 mod hit;
+mod image;
+mod light;
 mod material;
+mod medium;
+mod mesh;
+mod microfacet;
+mod obj;
+mod output;
+mod perlin;
 mod plane;
+mod principled;
 mod ray;
+mod rectanglexy;
+mod scene;
+mod sky;
+mod spectrum;
 mod sphere;
+mod texture;
+mod tonemap;
+mod triangle;
 mod vec;
 
+use background::{Background, BackgroundSettings};
 use camera::CameraSettings;
-use plane::Plane;
+use hdr::HdrImage;
+use medium::{Fog, FogSettings};
 use rand::Rng;
 use rayon::prelude::*;
+use scene::{construct_scene_from_settings, SceneSettings};
 use serde::Deserialize;
+use spectrum::{at_wavelength, SpectrumToRgb, LAMBDA_MAX, LAMBDA_MIN};
+use std::error::Error;
 use std::io::BufReader;
-use std::io::Write;
-use std::{env, fs::File};
-use vec::Vec3;
+use std::path::Path;
+use std::{env, fs::File, process};
 
-use material::{Dielectric, Lambertian, Metal};
+use output::{ImageWriter, OutputSettings};
 use ray::Ray;
 use rayon::iter::IntoParallelIterator;
-use std::sync::Arc;
-use vec::{Color, Point3};
+use vec::Color;
 
+use bvh::Bvh;
 use camera::Camera;
-use hit::{Hit, World};
-use sphere::Sphere;
+use hit::{Hit, HitRecord};
+use light::{power_heuristic, LightSample, Lights};
+use tonemap::{ToneMapSettings, ToneMapper};
 
 #[derive(Deserialize)]
 struct Preset {
@@ -33,288 +61,284 @@ struct Preset {
     samples_per_pixel: u64,
     max_depth: u64,
     camera: CameraSettings,
+    background: Option<BackgroundSettings>,
     scene: Option<SceneSettings>,
+    output: Option<OutputSettings>,
+    tone_mapping: Option<ToneMapSettings>,
+    // Trace a single wavelength per sample instead of red, green and blue at once, so that
+    // dispersive materials split light into colours. Off by default.
+    spectral: Option<bool>,
+    fog: Option<FogSettings>,
+    // After this many bounces, paths carrying little light are cut short at random (Russian
+    // roulette), which lets `max_depth` be high without every path going that deep. Off by
+    // default.
+    russian_roulette_depth: Option<u64>,
 }
 
-#[derive(Deserialize)]
-struct MaterialSettings {
-    dielectric: Option<Dielectric>,
-    lambertian: Option<Lambertian>,
-    metal: Option<Metal>,
+// Everything rays can run into.
+struct Scene {
+    world: Bvh,
+    lights: Lights,
+    background: Background,
+    fog: Option<Fog>,
+    // Russian roulette applies once a path has at most this many bounces left, which is after the
+    // preset's `russian_roulette_depth` bounces out of `max_depth`.
+    roulette_below_depth: Option<u64>,
 }
 
-#[derive(Deserialize)]
-struct SceneSettings {
-    spheres: Option<Vec<SphereSettings>>,
-    planes: Option<Vec<PlaneSettings>>,
-}
-
-#[derive(Deserialize)]
-struct SphereSettings {
-    center: Point3,
-    radius: f64,
-    material: MaterialSettings,
+// Russian roulette, for a path about to bounce on carrying `throughput` of the light it finds:
+// None if it stops here, or else the factor that makes up for the paths that did. Paths carrying
+// less light are more likely to stop, but none are certain to.
+fn roulette(throughput: Color) -> Option<f64> {
+    let q = (1.0 - throughput.x().max(throughput.y()).max(throughput.z())).max(0.05);
+    if rand::thread_rng().gen::<f64>() < q {
+        None
+    } else {
+        Some(1.0 / (1.0 - q))
+    }
 }
 
-#[derive(Deserialize)]
-struct PlaneSettings {
-    point1: Point3,
-    point2: Point3,
-    normal: Vec3,
-    material: MaterialSettings,
+// Scales `attenuation` for one more bounce of a path carrying `throughput`, by Russian roulette if
+// it's deep enough; None if the path stops.
+fn continue_path(
+    scene: &Scene,
+    depth: u64,
+    throughput: Color,
+    attenuation: Color,
+) -> Option<Color> {
+    match scene.roulette_below_depth {
+        Some(below_depth) if depth <= below_depth => {
+            roulette(throughput * attenuation).map(|scale| scale * attenuation)
+        }
+        _ => Some(attenuation),
+    }
 }
 
-fn ray_color(r: &Ray, world: &World, depth: u64) -> Color {
-    if depth <= 0 {
+// `bsdf_pdf` is the density with which the material that scattered `r` picked its direction, if
+// it has one and the lights were also sampled there. Light the ray then finds is weighted against
+// the chance of having found it that way instead (multiple importance sampling). `throughput` is
+// how much of that light makes it back to the camera.
+fn ray_color(
+    r: &Ray,
+    scene: &Scene,
+    depth: u64,
+    bsdf_pdf: Option<f64>,
+    throughput: Color,
+) -> Color {
+    if depth == 0 {
         // If we've exceeded the ray bounce limit, no more light is gathered
         return Color::new(0.0, 0.0, 0.0);
     }
-    if let Some(rec) = world.hit(r, 0.001, f64::INFINITY) {
-        if let Some((attenuation, scattered)) = rec.mat.scatter(r, &rec) {
-            attenuation * ray_color(&scattered, world, depth - 1)
-        } else {
-            Color::new(0.0, 0.0, 0.0)
+    // In spectral mode every colour is turned into its value at the ray's wavelength.
+    let wavelength = r.wavelength();
+    let hit = scene.world.hit(r, 0.001, f64::INFINITY);
+
+    // The ray may run into the fog before it gets to whatever it hits.
+    if let Some(fog) = &scene.fog {
+        if let Some((attenuation, scattered)) = fog.scatter(r, hit.as_ref().map(|rec| rec.t)) {
+            let scattered = scattered.with_wavelength(wavelength);
+            let attenuation = at_wavelength(attenuation, wavelength);
+            return match continue_path(scene, depth, throughput, attenuation) {
+                Some(attenuation) => {
+                    attenuation
+                        * ray_color(&scattered, scene, depth - 1, None, throughput * attenuation)
+                }
+                None => Color::new(0.0, 0.0, 0.0),
+            };
         }
-    } else {
-        let unit_direction = r.direction().normalized();
-        let background_color = Color::new(0.6, 0.75, 0.6);
-        // let background_color = Color::new(0.5, 0.7, 1.0);
-        let t = 0.5 * (unit_direction.y() + 1.0);
-        (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * background_color
     }
-}
-fn random_scene() -> World {
-    let mut rng = rand::thread_rng();
-    let mut world = World::new();
-
-    // let ground_mat = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
-    let ground_mat = Arc::new(Metal::new(Color::new(0.9, 0.6, 0.5), 0.1));
-    let ground_sphere = Sphere::new(Point3::new(0.0, -1000.0, 0.0), 1000.0, ground_mat);
-
-    world.push(Box::new(ground_sphere));
-
-    for a in -3..=7 {
-        for b in -3..=7 {
-            let choose_mat: f64 = rng.gen();
-            let center = Point3::new(
-                (a as f64) + rng.gen_range(0.0..0.9),
-                0.2,
-                (b as f64) + rng.gen_range(0.0..0.9),
-            );
 
-            if choose_mat < 0.8 {
-                // Diffuse
-                let albedo = Color::random(0.0..1.0) * Color::random(0.0..1.0);
-                let sphere_mat = Arc::new(Lambertian::new(albedo));
-                let sphere = Sphere::new(center, 0.2, sphere_mat);
-
-                world.push(Box::new(sphere));
-            } else if choose_mat < 0.95 {
-                // Metal
-                let albedo = Color::random(0.4..1.0);
-                let fuzz = rng.gen_range(0.0..0.5);
-                let sphere_mat = Arc::new(Metal::new(albedo, fuzz));
-                let sphere = Sphere::new(center, 0.2, sphere_mat);
-
-                world.push(Box::new(sphere));
-            } else {
-                // Glass
-                let sphere_mat = Arc::new(Dielectric::new(1.5));
-                let sphere = Sphere::new(center, 0.2, sphere_mat);
-
-                world.push(Box::new(sphere));
+    let rec = match hit {
+        Some(rec) => rec,
+        None => {
+            // The background can be a light too, like the sun.
+            let mut background = at_wavelength(scene.background.color(r.direction()), wavelength);
+            if let Some(bsdf_pdf) = bsdf_pdf {
+                let light_pdf = scene.lights.area_pdf(r, f64::INFINITY);
+                background = power_heuristic(bsdf_pdf, light_pdf) * background;
             }
+            return background;
         }
+    };
+
+    let mut emitted = at_wavelength(rec.mat.emitted(r, &rec), wavelength);
+    if let Some(bsdf_pdf) = bsdf_pdf {
+        let light_pdf = scene.lights.area_pdf(r, rec.t);
+        emitted = power_heuristic(bsdf_pdf, light_pdf) * emitted;
     }
 
-    // let cube_mat = Arc::new(Metal::new(Color::new(0.7, 0.6, 0.5), 0.0));
-
-    // A cube is made of 6 sides. For simplicity, I will make these be perpendicular to the axes.
-    // {x, y, z}{min, max} are the bounding box for the rectangular prism that we are going to draw.
-    // todo probably should extract this function.
-    let xmin = 3.0;
-    let xmax = 4.0;
-    let ymin = 0.0;
-    let ymax = 1.0;
-    let zmin = -0.5;
-    let zmax = 0.5;
-
-    // let cube_material = Arc::new(Lambertian::new(Color::new(0.2, 0.8, 0.2)));
-    let cube_material = Arc::new(Metal::new(Color::new(0.3, 0.2, 0.1), 0.0));
-
-    world.push(Box::new(Plane::new(
-        Vec3::new(0.0, 0.0, 1.0),
-        Vec3::new(xmin, ymin, zmin),
-        Vec3::new(xmax, ymax, zmin),
-        cube_material.clone(),
-    )));
-    world.push(Box::new(Plane::new(
-        Vec3::new(0.0, 0.0, 1.0),
-        Vec3::new(xmin, ymin, zmax),
-        Vec3::new(xmax, ymax, zmax),
-        cube_material.clone(),
-    )));
-    world.push(Box::new(Plane::new(
-        Vec3::new(0.0, 1.0, 0.0),
-        Vec3::new(xmin, ymin, zmin),
-        Vec3::new(xmax, ymin, zmax),
-        cube_material.clone(),
-    )));
-    world.push(Box::new(Plane::new(
-        Vec3::new(0.0, 1.0, 0.0),
-        Vec3::new(xmin, ymax, zmin),
-        Vec3::new(xmax, ymax, zmax),
-        cube_material.clone(),
-    )));
-    world.push(Box::new(Plane::new(
-        Vec3::new(1.0, 0.0, 0.0),
-        Vec3::new(xmin, ymin, zmin),
-        Vec3::new(xmin, ymax, zmax),
-        cube_material.clone(),
-    )));
-    world.push(Box::new(Plane::new(
-        Vec3::new(1.0, 0.0, 0.0),
-        Vec3::new(xmax, ymin, zmin),
-        Vec3::new(xmax, ymax, zmax),
-        cube_material.clone(),
-    )));
-
-    // let mat1 = Arc::new(Dielectric::new(1.5));
-    // let mat2 = Arc::new(Lambertian::new(Color::new(0.4, 0.2, 0.1)));
-    // let mat3 = Arc::new(Metal::new(Color::new(0.7, 0.6, 0.5), 0.0));
-
-    // let sphere1 = Sphere::new(Point3::new(0.0, 1.0, 0.0), 1.0, mat1);
-    // let sphere2 = Sphere::new(Point3::new(-4.0, 1.0, 0.0), 1.0, mat2);
-    // let sphere3 = Sphere::new(Point3::new(4.0, 1.0, 0.0), 1.0, mat3);
-
-    // world.push(Box::new(sphere1));
-    // world.push(Box::new(sphere2));
-    // world.push(Box::new(sphere3));
-
-    // let mat1 = Arc::new(Dielectric::new(1.5));
-    // let mat2 = Arc::new(Lambertian::new(Color::new(0.4, 0.2, 0.1)));
-    // let mat2_copy = Arc::new(Lambertian::new(Color::new(0.4, 0.2, 0.1)));
-    // let mat3 = Arc::new(Metal::new(Color::new(0.7, 0.6, 0.5), 0.0));
-
-    // let sphere1 = Sphere::new(Point3::new(0.0, 1.0, 0.0), 1.0, mat1);
-    // let sphere2 = Sphere::new(Point3::new(-4.0, 1.0, 0.0), 1.0, mat2);
-    // let sphere2_int = Sphere::new(Point3::new(-4.0, 1.0, 0.0), -0.99, mat2_copy);
-    // let sphere3 = Sphere::new(Point3::new(4.0, 1.0, 0.0), 1.0, mat3);
-
-    // world.push(Box::new(sphere1));
-    // world.push(Box::new(sphere2));
-    // world.push(Box::new(sphere2_int));
-    // world.push(Box::new(sphere3));
-
-    world
+    // The light reached straight from here is one bounce more, so it has to fit in too. It comes
+    // through `eval`, which leaves out mirror-like lobes: those only see lights their scattered
+    // rays run into, counted in full as they have no density to weigh against, and so never
+    // punctual ones, whose reflections are infinitely small.
+    let lit = depth > 1 && !scene.lights.is_empty();
+    let direct = if lit {
+        direct_light(r, &rec, scene)
+    } else {
+        Color::new(0.0, 0.0, 0.0)
+    };
+
+    if let Some(srec) = rec.mat.scatter(r, &rec) {
+        let scattered = srec.scattered.with_wavelength(wavelength);
+        let scattered_pdf = if lit { srec.pdf } else { None };
+        let attenuation = at_wavelength(srec.attenuation, wavelength);
+        let indirect = match continue_path(scene, depth, throughput, attenuation) {
+            Some(attenuation) => {
+                attenuation
+                    * ray_color(
+                        &scattered,
+                        scene,
+                        depth - 1,
+                        scattered_pdf,
+                        throughput * attenuation,
+                    )
+            }
+            None => Color::new(0.0, 0.0, 0.0),
+        };
+        emitted + direct + indirect
+    } else {
+        emitted + direct
+    }
 }
 
-fn construct_scene_from_settings(scene_settings: &Option<SceneSettings>) -> World {
-    if scene_settings.is_some() {
-        let scene_settings = scene_settings.as_ref().unwrap();
-        let mut world = World::new();
-
-        if scene_settings.spheres.is_some() {
-            let sphere_settings = scene_settings.spheres.as_ref().unwrap();
-            for sphere_setting in sphere_settings {
-                if sphere_setting.material.metal.is_some() {
-                    let metal_settings = sphere_setting.material.metal.as_ref().unwrap();
-                    let metal_mat =
-                        Arc::new(Metal::new(metal_settings.albedo, metal_settings.fuzz));
-                    world.push(Box::new(Sphere::new(
-                        sphere_setting.center,
-                        sphere_setting.radius,
-                        metal_mat,
-                    )));
-                }
-                if sphere_setting.material.lambertian.is_some() {
-                    let lambertian_settings = sphere_setting.material.lambertian.as_ref().unwrap();
-                    let lambertian_mat = Arc::new(Lambertian::new(lambertian_settings.albedo));
-                    world.push(Box::new(Sphere::new(
-                        sphere_setting.center,
-                        sphere_setting.radius,
-                        lambertian_mat,
-                    )));
-                }
-                if sphere_setting.material.dielectric.is_some() {
-                    let dielectric_settings = sphere_setting.material.dielectric.as_ref().unwrap();
-                    let dielectric_mat = Arc::new(Dielectric::new(dielectric_settings.ir));
-                    world.push(Box::new(Sphere::new(
-                        sphere_setting.center,
-                        sphere_setting.radius,
-                        dielectric_mat,
-                    )));
-                }
-            }
-        }
-        if scene_settings.planes.is_some() {
-            let plane_settings = scene_settings.planes.as_ref().unwrap();
-            for plane_setting in plane_settings {
-                if plane_setting.material.metal.is_some() {
-                    let metal_settings = plane_setting.material.metal.as_ref().unwrap();
-                    let metal_mat =
-                        Arc::new(Metal::new(metal_settings.albedo, metal_settings.fuzz));
-                    world.push(Box::new(Plane::new(
-                        plane_setting.normal,
-                        plane_setting.point1,
-                        plane_setting.point2,
-                        metal_mat,
-                    )));
-                }
-                if plane_setting.material.lambertian.is_some() {
-                    let lambertian_settings = plane_setting.material.lambertian.as_ref().unwrap();
-                    let lambertian_mat = Arc::new(Lambertian::new(lambertian_settings.albedo));
-                    world.push(Box::new(Plane::new(
-                        plane_setting.normal,
-                        plane_setting.point1,
-                        plane_setting.point2,
-                        lambertian_mat,
-                    )));
-                }
-                if plane_setting.material.dielectric.is_some() {
-                    let dielectric_settings = plane_setting.material.dielectric.as_ref().unwrap();
-                    let dielectric_mat = Arc::new(Dielectric::new(dielectric_settings.ir));
-                    world.push(Box::new(Plane::new(
-                        plane_setting.normal,
-                        plane_setting.point1,
-                        plane_setting.point2,
-                        dielectric_mat,
-                    )));
-                }
-            }
+// Next-event estimation: the light arriving at `rec` straight from a randomly sampled point on
+// one of the area lights, plus that from every punctual light.
+fn direct_light(r: &Ray, rec: &HitRecord, scene: &Scene) -> Color {
+    let mut direct = Color::new(0.0, 0.0, 0.0);
+    if let Some(sample) = scene.lights.sample_area(rec.p) {
+        direct += light_sample_contribution(r, rec, scene, &sample, true);
+    }
+    for light in &scene.lights.punctual {
+        if let Some(sample) = light.sample(rec.p) {
+            direct += light_sample_contribution(r, rec, scene, &sample, false);
         }
-        world
-    } else {
-        random_scene()
     }
+    direct
 }
 
-fn load_preset_from_file(path_to_file: &str) -> Preset {
-    let file = File::open(path_to_file).unwrap();
+// The light `sample` brings to `rec`, if nothing's in the way. With `mis` it is weighted against
+// the chance of the material having scattered towards it.
+fn light_sample_contribution(
+    r: &Ray,
+    rec: &HitRecord,
+    scene: &Scene,
+    sample: &LightSample,
+    mis: bool,
+) -> Color {
+    let wavelength = r.wavelength();
+    let f = rec.mat.eval(r, rec, sample.direction);
+    if f.near_zero() {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+
+    let shadow = Ray::new(rec.p, sample.direction).with_wavelength(wavelength);
+    let mut transmittance = scene
+        .world
+        .transmittance(&shadow, 0.001, sample.distance - 0.001);
+    if let Some(fog) = &scene.fog {
+        transmittance *= fog.transmittance(sample.distance);
+    }
+    if transmittance <= 0.0 {
+        return Color::new(0.0, 0.0, 0.0);
+    }
+
+    let weight = if mis {
+        power_heuristic(sample.pdf, rec.mat.pdf(r, rec, sample.direction))
+    } else {
+        1.0
+    };
+    (transmittance * weight / sample.pdf)
+        * at_wavelength(f, wavelength)
+        * at_wavelength(sample.radiance, wavelength)
+}
+
+fn load_preset_from_file(path_to_file: &str) -> Result<Preset, Box<dyn Error>> {
+    let file = File::open(path_to_file)?;
     let reader = BufReader::new(file);
 
-    let u: Preset = serde_json::from_reader(reader).unwrap();
-    u
+    let u: Preset = serde_json::from_reader(reader)?;
+    Ok(u)
 }
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    let preset: Preset = load_preset_from_file(&args[1]);
+    let preset: Preset = match load_preset_from_file(&args[1]) {
+        Ok(preset) => preset,
+        Err(e) => {
+            eprintln!("Could not read the preset {}: {}", args[1], e);
+            process::exit(1);
+        }
+    };
 
     // image
     let image_height: u64 = ((preset.image_width as f64) / preset.camera.aspect_ratio) as u64;
 
     // World
     // let world = random_scene();
-    let world = construct_scene_from_settings(&preset.scene);
+    let (world, mut lights) = match construct_scene_from_settings(&preset.scene) {
+        Ok(scene) => scene,
+        Err(e) => {
+            eprintln!("Could not build the scene: {}", e);
+            process::exit(1);
+        }
+    };
 
-    let cam = Camera::new(&preset.camera);
+    let background = match Background::from_settings(&preset.background) {
+        Ok(background) => background,
+        Err(e) => {
+            eprintln!("Could not load the background: {}", e);
+            process::exit(1);
+        }
+    };
+    if let Some(light) = background.light() {
+        lights.area.push(light);
+    }
+
+    let tone_mapper = match ToneMapper::new(&preset.tone_mapping.unwrap_or_default()) {
+        Ok(tone_mapper) => tone_mapper,
+        Err(e) => {
+            eprintln!("Could not set up tone mapping: {}", e);
+            process::exit(1);
+        }
+    };
+    let image_writer = match ImageWriter::new(
+        Path::new(&args[2]),
+        &preset.output.unwrap_or_default(),
+        tone_mapper,
+    ) {
+        Ok(image_writer) => image_writer,
+        Err(e) => {
+            eprintln!("Could not write the image: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let fog = match preset.fog.as_ref().map(Fog::new).transpose() {
+        Ok(fog) => fog,
+        Err(e) => {
+            eprintln!("Could not build the scene: {}", e);
+            process::exit(1);
+        }
+    };
 
-    let mut output = File::create(&args[2]).unwrap();
-    writeln!(output, "P3").unwrap();
-    writeln!(output, "{} {}", preset.image_width, image_height).unwrap();
-    writeln!(output, "255").unwrap();
+    let cam = Camera::new(&preset.camera);
+    let spectrum_to_rgb = preset.spectral.unwrap_or(false).then(SpectrumToRgb::new);
+    let scene = Scene {
+        world,
+        lights,
+        background,
+        fog,
+        roulette_below_depth: preset
+            .russian_roulette_depth
+            .map(|depth| preset.max_depth.saturating_sub(depth)),
+    };
+
+    let mut framebuffer = HdrImage {
+        width: preset.image_width as usize,
+        height: image_height as usize,
+        pixels: Vec::with_capacity((preset.image_width * image_height) as usize),
+    };
 
     for j in (0..image_height).rev() {
         eprint!("\rScanlines remaining: {}", j + 1);
@@ -325,7 +349,8 @@ fn main() {
                 let mut rng = rand::thread_rng();
 
                 let mut pixel_color = Color::new(0.0, 0.0, 0.0);
-                for _ in 0..preset.samples_per_pixel {
+                let white = Color::new(1.0, 1.0, 1.0);
+                for s in 0..preset.samples_per_pixel {
                     let random_u: f64 = rng.gen();
                     let random_v: f64 = rng.gen();
 
@@ -333,21 +358,115 @@ fn main() {
                     let v = ((j as f64) + random_v) / ((image_height - 1) as f64);
 
                     let r = cam.get_ray(u, v);
-                    pixel_color += ray_color(&r, &world, preset.max_depth);
+                    pixel_color += match &spectrum_to_rgb {
+                        Some(spectrum_to_rgb) => {
+                            // Spread each pixel's samples evenly over the spectrum, which
+                            // keeps the colour noise down.
+                            let lambda = LAMBDA_MIN
+                                + (s as f64 + rng.gen::<f64>()) / preset.samples_per_pixel as f64
+                                    * (LAMBDA_MAX - LAMBDA_MIN);
+                            let r = r.with_wavelength(Some(lambda));
+                            let radiance = ray_color(&r, &scene, preset.max_depth, None, white);
+                            spectrum_to_rgb.to_rgb(lambda, radiance.x())
+                        }
+                        None => ray_color(&r, &scene, preset.max_depth, None, white),
+                    };
                 }
 
                 pixel_color
             })
             .collect();
 
-        for pixel_color in scanline {
-            writeln!(
-                output,
-                "{}",
-                pixel_color.format_color(preset.samples_per_pixel)
-            )
-            .unwrap();
-        }
+        framebuffer.pixels.extend(
+            scanline
+                .into_iter()
+                .map(|pixel_color| pixel_color / (preset.samples_per_pixel as f64)),
+        );
     }
     eprintln!();
+
+    if let Err(e) = image_writer.write(&framebuffer) {
+        eprintln!("Could not write the image: {}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hit::World;
+    use light::PointLight;
+    use microfacet::{Conductor, RoughConductor};
+    use std::sync::Arc;
+    use vec::{Point3, Vec3};
+
+    fn empty_scene(lights: Lights, roulette_below_depth: Option<u64>) -> Scene {
+        Scene {
+            world: Bvh::new(World::new()),
+            lights,
+            background: Background::Solid(Color::new(0.0, 0.0, 0.0)),
+            fog: None,
+            roulette_below_depth,
+        }
+    }
+
+    #[test]
+    fn roulette_is_unbiased() {
+        let n = 200_000;
+        for t in [0.1, 0.5, 1.0, 2.5] {
+            let throughput = Color::new(t, 0.5 * t, 0.0);
+            let mean: f64 = (0..n).filter_map(|_| roulette(throughput)).sum::<f64>() / n as f64;
+            assert!(
+                (mean - 1.0).abs() < 0.03,
+                "{} for a throughput of {}",
+                mean,
+                t
+            );
+        }
+    }
+
+    #[test]
+    fn roulette_waits_for_its_depth() {
+        let scene = empty_scene(Lights::new(), Some(3));
+        let throughput = Color::new(0.01, 0.01, 0.01);
+        let attenuation = Color::new(0.5, 0.5, 0.5);
+        // With more than 3 bounces left every path goes on as it is.
+        for _ in 0..1000 {
+            let scaled = continue_path(&scene, 4, throughput, attenuation).unwrap();
+            assert!((scaled - attenuation).near_zero());
+        }
+        // After that, dark paths mostly stop.
+        let stopped = (0..1000)
+            .filter(|_| continue_path(&scene, 3, throughput, attenuation).is_none())
+            .count();
+        assert!(stopped > 900);
+    }
+
+    #[test]
+    fn rough_metals_are_lit_by_point_lights() {
+        let mut lights = Lights::new();
+        lights.punctual.push(Box::new(PointLight::new(
+            Point3::new(0.0, 2.0, 0.0),
+            Color::new(10.0, 10.0, 10.0),
+        )));
+        let scene = empty_scene(lights, None);
+
+        let (eta, k) = Conductor::Gold.ior();
+        let rec = HitRecord {
+            p: Point3::new(0.0, 0.0, 0.0),
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            shading_normal: Vec3::new(0.0, 1.0, 0.0),
+            mat: Arc::new(RoughConductor::new(eta, k, 0.5)),
+            t: 1.0,
+            u: 0.0,
+            v: 0.0,
+            dpdu: Vec3::new(1.0, 0.0, 0.0),
+            dpdv: Vec3::new(0.0, 0.0, 1.0),
+            front_face: true,
+        };
+        // Seen at 45 degrees, the light straight above is well inside the reflection's lobe.
+        let r = Ray::new(Point3::new(-1.0, 1.0, 0.0), Vec3::new(1.0, -1.0, 0.0));
+        let direct = direct_light(&r, &rec, &scene);
+        assert!(direct.x() > 0.0 && direct.y() > 0.0);
+    }
 }
@@ -1,71 +1,219 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+
 use rand::Rng;
-use serde::Deserialize;
 
 use super::hit::HitRecord;
+use super::microfacet::Frame;
 use super::ray::Ray;
+use super::texture::{SolidColor, Texture};
 use super::vec::{Color, Vec3};
 
+// A direction sampled by a material.
+pub struct ScatterRecord {
+    // The BSDF times the cosine, over the density of picking `scattered`.
+    pub attenuation: Color,
+    pub scattered: Ray,
+    // That density, or None when the material can't evaluate it: for mirrors and glass, which
+    // scatter into a single direction, and for the materials that only know how to sample.
+    pub pdf: Option<f64>,
+}
+
 pub trait Scatter: Send + Sync {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)>;
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord>;
+
+    // Light given off by the surface itself, towards where r_in came from. Most materials don't
+    // emit anything.
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    // The BSDF times the cosine for light leaving along `direction`. Only materials whose
+    // `scatter` gives a density need this.
+    fn eval(&self, _r_in: &Ray, _rec: &HitRecord, _direction: Vec3) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+
+    // The density with which `scatter` picks `direction`.
+    fn pdf(&self, _r_in: &Ray, _rec: &HitRecord, _direction: Vec3) -> f64 {
+        0.0
+    }
 }
 
-#[derive(Deserialize)]
 pub struct Lambertian {
-    pub albedo: Color,
+    pub albedo: Arc<dyn Texture>,
 }
 
 impl Lambertian {
     pub fn new(albedo: Color) -> Lambertian {
+        Lambertian::from_texture(Arc::new(SolidColor::new(albedo)))
+    }
+
+    pub fn from_texture(albedo: Arc<dyn Texture>) -> Lambertian {
         Lambertian { albedo }
     }
 }
 
 impl Scatter for Lambertian {
-    fn scatter(&self, _r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
-        let mut scatter_direction = rec.normal + Vec3::random_in_unit_sphere().normalized();
-        if scatter_direction.near_zero() {
-            scatter_direction = rec.normal;
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let direction = Frame::new(rec.shading_normal).to_world(Vec3::random_cosine_direction());
+        let pdf = self.pdf(r_in, rec, direction);
+        if pdf <= 0.0 {
+            return None;
         }
-        let scattered = Ray::new(rec.p, scatter_direction);
 
-        Some((self.albedo, scattered))
+        // The BSDF is albedo / pi, which the cosine-weighted density cancels out but for the
+        // albedo.
+        Some(ScatterRecord {
+            attenuation: self.albedo.value(rec.u, rec.v, rec.p),
+            scattered: Ray::new(rec.p, direction),
+            pdf: Some(pdf),
+        })
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Color {
+        // No light gets through from below the actual surface.
+        if direction.dot(rec.normal) <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        self.pdf(r_in, rec, direction) * self.albedo.value(rec.u, rec.v, rec.p)
+    }
+
+    fn pdf(&self, _r_in: &Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+        rec.shading_normal.dot(direction.normalized()).max(0.0) / PI
     }
 }
 
-#[derive(Deserialize)]
 pub struct Metal {
-    pub albedo: Color,
+    pub albedo: Arc<dyn Texture>,
     pub fuzz: f64,
 }
 
 impl Metal {
     pub fn new(albedo: Color, fuzz: f64) -> Metal {
+        Metal::from_texture(Arc::new(SolidColor::new(albedo)), fuzz)
+    }
+
+    pub fn from_texture(albedo: Arc<dyn Texture>, fuzz: f64) -> Metal {
         Metal { albedo, fuzz }
     }
 }
 
 impl Scatter for Metal {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
-        let reflected = r_in.direction().reflect(rec.normal).normalized();
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let reflected = r_in.direction().reflect(rec.shading_normal).normalized();
         let scattered = Ray::new(rec.p, reflected + self.fuzz * Vec3::random_in_unit_sphere());
 
+        // Reflections that would go into the actual surface are absorbed.
         if scattered.direction().dot(rec.normal) > 0.0 {
-            Some((self.albedo, scattered))
+            // Without fuzz this is a perfect mirror.
+            let pdf = (self.fuzz > 0.0).then(|| self.pdf(r_in, rec, scattered.direction()));
+            Some(ScatterRecord {
+                attenuation: self.albedo.value(rec.u, rec.v, rec.p),
+                scattered,
+                pdf,
+            })
         } else {
             None
         }
     }
+
+    // Every direction `scatter` keeps is weighted by the albedo alone, so this is the albedo
+    // times the density.
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Color {
+        if direction.dot(rec.normal) <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        self.pdf(r_in, rec, direction) * self.albedo.value(rec.u, rec.v, rec.p)
+    }
+
+    // The directions `scatter` picks point at a uniformly random point in the ball of radius
+    // `fuzz` around the tip of the unit reflection. The density along a direction is the part of
+    // that ball's volume it passes through, r^2 dr integrated from where it enters (r1) to where
+    // it leaves (r2), over the whole volume.
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+        if self.fuzz <= 0.0 {
+            return 0.0;
+        }
+        let reflected = r_in.direction().reflect(rec.shading_normal).normalized();
+        let b = direction.normalized().dot(reflected);
+        let discriminant = b * b - (1.0 - self.fuzz * self.fuzz);
+        if discriminant < 0.0 {
+            return 0.0;
+        }
+        let r2 = b + discriminant.sqrt();
+        if r2 <= 0.0 {
+            return 0.0;
+        }
+        // From inside the ball, which big enough fuzz puts us, rays start in it.
+        let r1 = (b - discriminant.sqrt()).max(0.0);
+        (r2.powi(3) - r1.powi(3)) / (4.0 * PI * self.fuzz.powi(3))
+    }
+}
+
+// An index of refraction, possibly varying with wavelength (in nm), which is what disperses light
+// into colours. Formulas take the wavelength in micrometres, as they're usually published.
+pub enum Ior {
+    Constant(f64),
+    // n = a + b / lambda^2
+    Cauchy { a: f64, b: f64 },
+    // n^2 = 1 + sum of b_i lambda^2 / (lambda^2 - c_i)
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Ior {
+    // Without a wavelength, at the yellow helium d line that glass catalogues quote.
+    pub fn at(&self, wavelength: Option<f64>) -> f64 {
+        let lambda = wavelength.unwrap_or(587.6) / 1000.0;
+        let l2 = lambda * lambda;
+        match self {
+            Ior::Constant(n) => *n,
+            Ior::Cauchy { a, b } => a + b / l2,
+            Ior::Sellmeier { b, c } => {
+                let sum: f64 = (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum();
+                (1.0 + sum).sqrt()
+            }
+        }
+    }
 }
 
-#[derive(Deserialize)]
 pub struct Dielectric {
     // index of refraction
-    pub ir: f64,
+    pub ir: Ior,
+    // How much of each colour the inside absorbs per unit of distance, following Beer-Lambert.
+    // Clear glass absorbs nothing.
+    pub absorption: Color,
 }
 
 impl Dielectric {
-    pub fn new(ir: f64) -> Dielectric {
-        Dielectric { ir }
+    pub fn new(ir: Ior) -> Dielectric {
+        Dielectric {
+            ir,
+            absorption: Color::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    // Glass that tints white light to `color` over `distance` travelled inside it.
+    pub fn tinted(ir: Ior, color: Color, distance: f64) -> Dielectric {
+        let coefficient = |c: f64| -c.clamp(1.0e-6, 1.0).ln() / distance;
+        Dielectric {
+            ir,
+            absorption: Color::new(
+                coefficient(color.x()),
+                coefficient(color.y()),
+                coefficient(color.z()),
+            ),
+        }
+    }
+
+    // The share of light left after travelling `distance` inside.
+    fn transmittance(&self, distance: f64) -> Color {
+        let channel = |a: f64| if a > 0.0 { (-a * distance).exp() } else { 1.0 };
+        Color::new(
+            channel(self.absorption.x()),
+            channel(self.absorption.y()),
+            channel(self.absorption.z()),
+        )
     }
 
     fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
@@ -76,16 +224,13 @@ impl Dielectric {
 }
 
 impl Scatter for Dielectric {
-    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<(Color, Ray)> {
-        let refraction_ratio = if rec.front_face {
-            1.0 / self.ir
-        } else {
-            self.ir
-        };
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let ir = self.ir.at(r_in.wavelength());
+        let refraction_ratio = if rec.front_face { 1.0 / ir } else { ir };
 
         let unit_direction = r_in.direction().normalized();
 
-        let cos_theta = ((-1.0) * unit_direction).dot(rec.normal).min(1.0);
+        let cos_theta = ((-1.0) * unit_direction).dot(rec.shading_normal).min(1.0);
         let sin_theta = (1.0 - cos_theta.powi(2)).sqrt();
 
         let mut rng = rand::thread_rng();
@@ -93,13 +238,171 @@ impl Scatter for Dielectric {
         let will_reflect = rng.gen::<f64>() < Self::reflectance(cos_theta, refraction_ratio);
 
         let direction = if cannot_refract || will_reflect {
-            unit_direction.reflect(rec.normal)
+            unit_direction.reflect(rec.shading_normal)
         } else {
-            unit_direction.refract(rec.normal, refraction_ratio)
+            unit_direction.refract(rec.shading_normal, refraction_ratio)
         };
 
         let scattered = Ray::new(rec.p, direction);
 
-        Some((Color::new(1.0, 1.0, 1.0), scattered))
+        // Hitting the inside of the surface means the ray has just crossed the medium.
+        let attenuation = if rec.front_face {
+            Color::new(1.0, 1.0, 1.0)
+        } else {
+            self.transmittance(rec.t * r_in.direction().length())
+        };
+
+        Some(ScatterRecord {
+            attenuation,
+            scattered,
+            pdf: None,
+        })
+    }
+}
+
+// A light source: emits `emit` from both sides of the surface and reflects nothing.
+pub struct DiffuseLight {
+    pub emit: Color,
+}
+
+impl DiffuseLight {
+    pub fn new(emit: Color) -> DiffuseLight {
+        DiffuseLight { emit }
+    }
+}
+
+impl Scatter for DiffuseLight {
+    fn scatter(&self, _r_in: &Ray, _rec: &HitRecord) -> Option<ScatterRecord> {
+        None
+    }
+
+    fn emitted(&self, _r_in: &Ray, _rec: &HitRecord) -> Color {
+        self.emit
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::vec::Point3;
+
+    // A hit on a surface facing (1, 1, 0), seen along `ray`.
+    pub fn tilted_hit() -> (Ray, HitRecord) {
+        let normal = Vec3::new(1.0, 1.0, 0.0).normalized();
+        let rec = HitRecord {
+            p: Point3::new(0.0, 0.0, 0.0),
+            normal,
+            shading_normal: normal,
+            mat: Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+            t: 1.0,
+            u: 0.0,
+            v: 0.0,
+            dpdu: Vec3::new(0.0, 0.0, 1.0),
+            dpdv: Vec3::new(-1.0, 1.0, 0.0).normalized(),
+            front_face: true,
+        };
+        let ray = Ray::new(Point3::new(1.0, 2.0, 0.5), Vec3::new(-1.0, -2.0, -0.5));
+        (ray, rec)
+    }
+
+    // Checks `eval` and `pdf` against what `scatter` does: every direction it gives a density
+    // comes with the one `pdf` has for it, and over the directions above the surface (sampled
+    // uniformly) `pdf` adds up to how often it gives one, and `eval` to the light it passes on
+    // along those.
+    pub fn assert_density_agrees_with_sampling(mat: &dyn Scatter) {
+        let (r_in, rec) = tilted_hit();
+        let n = 200_000;
+        let (mut sampled, mut sampled_light) = (0.0, 0.0);
+        for _ in 0..n {
+            if let Some(srec) = mat.scatter(&r_in, &rec) {
+                if let Some(pdf) = srec.pdf {
+                    let expected = mat.pdf(&r_in, &rec, srec.scattered.direction());
+                    assert!((pdf - expected).abs() <= 1.0e-9 * expected);
+                    sampled += 1.0 / n as f64;
+                    sampled_light += srec.attenuation.x() / n as f64;
+                }
+            }
+        }
+
+        let (mut total, mut total_light) = (0.0, 0.0);
+        for _ in 0..n {
+            let direction = Vec3::random_in_unit_sphere().normalized();
+            // `scatter` absorbs what it picks below the surface.
+            if direction.dot(rec.normal) <= 0.0 {
+                continue;
+            }
+            total += 4.0 * PI * mat.pdf(&r_in, &rec, direction) / n as f64;
+            total_light += 4.0 * PI * mat.eval(&r_in, &rec, direction).x() / n as f64;
+        }
+        assert!(sampled > 0.01);
+        assert!(
+            (total - sampled).abs() < 0.05 * sampled,
+            "pdf integrates to {}, not {}",
+            total,
+            sampled
+        );
+        assert!(
+            (total_light - sampled_light).abs() < 0.05 * sampled_light,
+            "eval integrates to {}, not {}",
+            total_light,
+            sampled_light
+        );
+    }
+
+    #[test]
+    fn tinted_glass_absorbs_with_distance() {
+        let glass = Dielectric::tinted(Ior::Constant(1.5), Color::new(0.5, 1.0, 0.0), 2.0);
+        // Exactly the tint after `distance`, and its square after twice that.
+        let once = glass.transmittance(2.0);
+        let twice = glass.transmittance(4.0);
+        assert!((once.x() - 0.5).abs() < 1.0e-9);
+        assert!((twice.x() - 0.25).abs() < 1.0e-9);
+        assert_eq!(once.y(), 1.0);
+        assert!(once.z() < 1.0e-5);
+        // Nothing is lost over no distance at all.
+        assert!((glass.transmittance(0.0).z() - 1.0).abs() < 1.0e-9);
+    }
+
+    #[test]
+    fn glass_disperses_blue_more_than_red() {
+        let bk7 = Ior::Sellmeier {
+            b: [1.03961212, 0.231792344, 1.01046945],
+            c: [0.00600069867, 0.0200179144, 103.560653],
+        };
+        // The catalogue value for N-BK7 at the d line.
+        assert!((bk7.at(None) - 1.5168).abs() < 1.0e-4);
+        assert!(bk7.at(Some(450.0)) > bk7.at(Some(650.0)));
+    }
+
+    #[test]
+    fn lambertian_samples_agree_with_its_density() {
+        let lambertian = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+        let (r_in, rec) = tilted_hit();
+
+        let n = 100_000;
+        let mut mean_cos = 0.0;
+        for _ in 0..n {
+            let srec = lambertian.scatter(&r_in, &rec).unwrap();
+            let direction = srec.scattered.direction();
+            let pdf = lambertian.pdf(&r_in, &rec, direction);
+            assert!((srec.pdf.unwrap() - pdf).abs() < 1.0e-9);
+            // The weight is exactly the BSDF times the cosine over the density.
+            let f = lambertian.eval(&r_in, &rec, direction);
+            assert!((f.x() / pdf - srec.attenuation.x()).abs() < 1.0e-9);
+            mean_cos += rec.normal.dot(direction.normalized()) / n as f64;
+        }
+        // The mean cosine under the density cos / pi is 2/3.
+        assert!((mean_cos - 2.0 / 3.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn fuzzy_metal_samples_agree_with_its_density() {
+        assert_density_agrees_with_sampling(&Metal::new(Color::new(0.8, 0.8, 0.8), 0.5));
+        // With fuzz beyond 1 the ball of directions takes in where the rays start.
+        assert_density_agrees_with_sampling(&Metal::new(Color::new(0.8, 0.8, 0.8), 1.5));
+        // A sharp mirror has no density.
+        let (r_in, rec) = tilted_hit();
+        let mirror = Metal::new(Color::new(0.8, 0.8, 0.8), 0.0);
+        assert!(mirror.scatter(&r_in, &rec).unwrap().pdf.is_none());
     }
 }
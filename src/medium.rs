@@ -0,0 +1,393 @@
+use std::error::Error;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use rand::Rng;
+use serde::Deserialize;
+
+use super::aabb::Aabb;
+use super::grid::DensityGrid;
+use super::hit::{Hit, HitRecord};
+use super::material::{Scatter, ScatterRecord};
+use super::microfacet::Frame;
+use super::ray::Ray;
+use super::scene::SceneError;
+use super::texture::{SolidColor, Texture};
+use super::vec::{Color, Point3, Vec3};
+
+// Henyey and Greenstein's phase function: how a particle scatters light, from g = -1 (straight
+// back) through 0 (evenly in every direction) to 1 (straight on). It reflects `albedo` of the light.
+pub struct HenyeyGreenstein {
+    albedo: Arc<dyn Texture>,
+    g: f64,
+}
+
+impl HenyeyGreenstein {
+    pub fn new(albedo: Arc<dyn Texture>, g: f64) -> HenyeyGreenstein {
+        HenyeyGreenstein {
+            albedo,
+            // The sampling formula divides by g, and breaks down at +-1.
+            g: g.clamp(-0.999, 0.999),
+        }
+    }
+
+    pub fn isotropic(albedo: Arc<dyn Texture>) -> HenyeyGreenstein {
+        HenyeyGreenstein::new(albedo, 0.0)
+    }
+
+    // A new direction for light travelling along `incoming`, by inverting the phase function's
+    // distribution of cos(theta), where theta is the angle it turns by.
+    fn sample_direction(&self, incoming: Vec3) -> Vec3 {
+        let mut rng = rand::thread_rng();
+        let xi: f64 = rng.gen();
+        let cos_theta = if self.g.abs() < 1.0e-3 {
+            1.0 - 2.0 * xi
+        } else {
+            let g = self.g;
+            let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * xi);
+            (1.0 + g * g - s * s) / (2.0 * g)
+        }
+        .clamp(-1.0, 1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let phi = 2.0 * PI * rng.gen::<f64>();
+
+        Frame::new(incoming.normalized()).to_world(Vec3::new(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            cos_theta,
+        ))
+    }
+}
+
+impl Scatter for HenyeyGreenstein {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let direction = self.sample_direction(r_in.direction());
+        // Sampled exactly by the phase function, which is its own density.
+        Some(ScatterRecord {
+            attenuation: self.albedo.value(rec.u, rec.v, rec.p),
+            scattered: Ray::new(rec.p, direction),
+            pdf: Some(self.pdf(r_in, rec, direction)),
+        })
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Color {
+        self.pdf(r_in, rec, direction) * self.albedo.value(rec.u, rec.v, rec.p)
+    }
+
+    fn pdf(&self, r_in: &Ray, _rec: &HitRecord, direction: Vec3) -> f64 {
+        let cos_theta = r_in.direction().normalized().dot(direction.normalized());
+        let g = self.g;
+        let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+        (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+    }
+}
+
+// How far a ray (in units of its own direction's length) travels through a medium of `density`
+// before it runs into a particle.
+fn free_flight(r: &Ray, density: f64) -> f64 {
+    let u: f64 = rand::thread_rng().gen();
+    -(1.0 - u).ln() / (density * r.direction().length())
+}
+
+// A record for a scattering event inside a medium, where there is no surface and so no normal.
+fn medium_record(r: &Ray, t: f64, mat: Arc<dyn Scatter>) -> HitRecord {
+    let n = Vec3::new(1.0, 0.0, 0.0);
+    HitRecord {
+        p: r.at(t),
+        normal: n,
+        shading_normal: n,
+        mat,
+        t,
+        u: 0.0,
+        v: 0.0,
+        dpdu: Vec3::new(0.0, 1.0, 0.0),
+        dpdv: Vec3::new(0.0, 0.0, 1.0),
+        front_face: true,
+    }
+}
+
+// Smoke or fog of the same density throughout the inside of `boundary`, which needs to be a
+// closed shape. Rays scatter inside it with the `phase_function` material.
+pub struct ConstantMedium {
+    boundary: Box<dyn Hit>,
+    density: f64,
+    phase_function: Arc<dyn Scatter>,
+}
+
+impl ConstantMedium {
+    pub fn new(
+        boundary: Box<dyn Hit>,
+        density: f64,
+        phase_function: Arc<dyn Scatter>,
+    ) -> ConstantMedium {
+        ConstantMedium {
+            boundary,
+            density,
+            phase_function,
+        }
+    }
+}
+
+impl Hit for ConstantMedium {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        // Where the ray enters and leaves the boundary, even if it starts inside.
+        let entry = self.boundary.hit(r, f64::NEG_INFINITY, f64::INFINITY)?;
+        let exit = self.boundary.hit(r, entry.t + 0.0001, f64::INFINITY)?;
+
+        let t_enter = entry.t.max(t_min);
+        let t_exit = exit.t.min(t_max);
+        if t_enter >= t_exit {
+            return None;
+        }
+
+        let t = t_enter + free_flight(r, self.density);
+        if t >= t_exit {
+            return None;
+        }
+        Some(medium_record(r, t, self.phase_function.clone()))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.boundary.bounding_box()
+    }
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64) -> f64 {
+        match self.boundary.hit(r, f64::NEG_INFINITY, f64::INFINITY) {
+            Some(entry) => match self.boundary.hit(r, entry.t + 0.0001, f64::INFINITY) {
+                Some(exit) => {
+                    let inside = (exit.t.min(t_max) - entry.t.max(t_min)).max(0.0);
+                    (-self.density * inside * r.direction().length()).exp()
+                }
+                None => 1.0,
+            },
+            None => 1.0,
+        }
+    }
+}
+
+// Smoke or clouds whose density varies, given by a voxel grid stretched over `bounds` and scaled
+// by `scale`.
+pub struct GridMedium {
+    grid: DensityGrid,
+    bounds: Aabb,
+    scale: f64,
+    // The greatest density anywhere in the grid, which the tracking methods sample distances
+    // against as if the medium were that dense throughout.
+    majorant: f64,
+    phase_function: Arc<dyn Scatter>,
+}
+
+impl GridMedium {
+    pub fn new(
+        grid: DensityGrid,
+        bounds: Aabb,
+        scale: f64,
+        phase_function: Arc<dyn Scatter>,
+    ) -> GridMedium {
+        let majorant = scale * grid.max();
+        GridMedium {
+            grid,
+            bounds,
+            scale,
+            majorant,
+            phase_function,
+        }
+    }
+
+    fn density(&self, p: Point3) -> f64 {
+        self.scale * self.grid.density(self.bounds.relative(p))
+    }
+}
+
+impl Hit for GridMedium {
+    // Delta tracking: step through the medium as if it had the majorant density everywhere, and
+    // at each step hit a real particle with probability density / majorant, or carry on through
+    // a fictitious one.
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        if self.majorant <= 0.0 {
+            return None;
+        }
+        let (mut t, t_exit) = self.bounds.interval(r, t_min, t_max)?;
+        let mut rng = rand::thread_rng();
+        loop {
+            t += free_flight(r, self.majorant);
+            if t >= t_exit {
+                return None;
+            }
+            if rng.gen::<f64>() * self.majorant < self.density(r.at(t)) {
+                return Some(medium_record(r, t, self.phase_function.clone()));
+            }
+        }
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.bounds)
+    }
+    // Ratio tracking: the same steps as delta tracking, but instead of stopping at a particle,
+    // keep the fraction of light that gets past each one.
+    fn transmittance(&self, r: &Ray, t_min: f64, t_max: f64) -> f64 {
+        if self.majorant <= 0.0 {
+            return 1.0;
+        }
+        let (mut t, t_exit) = match self.bounds.interval(r, t_min, t_max) {
+            Some(interval) => interval,
+            None => return 1.0,
+        };
+        let mut transmittance = 1.0;
+        loop {
+            t += free_flight(r, self.majorant);
+            if t >= t_exit {
+                return transmittance;
+            }
+            transmittance *= 1.0 - self.density(r.at(t)) / self.majorant;
+        }
+    }
+}
+
+// Fog filling the whole scene.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FogSettings {
+    // The chance per unit of distance that light hits a particle.
+    density: f64,
+    // White by default.
+    albedo: Option<Color>,
+    // The Henyey-Greenstein asymmetry; 0 (isotropic) by default.
+    g: Option<f64>,
+    // Rays that leave the scene without hitting anything go through this much fog. Without it,
+    // they see the background clearly.
+    max_distance: Option<f64>,
+}
+
+pub struct Fog {
+    density: f64,
+    max_distance: f64,
+    phase_function: Arc<dyn Scatter>,
+}
+
+impl Fog {
+    pub fn new(settings: &FogSettings) -> Result<Fog, Box<dyn Error>> {
+        // Negative densities would scatter rays behind where they start.
+        if !(settings.density >= 0.0 && settings.density.is_finite()) {
+            return Err(Box::new(SceneError(format!(
+                "the fog's `density` needs to be a number that isn't negative, not {}",
+                settings.density
+            ))));
+        }
+        let albedo = Arc::new(SolidColor::new(
+            settings.albedo.unwrap_or(Color::new(1.0, 1.0, 1.0)),
+        ));
+        Ok(Fog {
+            density: settings.density,
+            max_distance: settings.max_distance.unwrap_or(0.0),
+            phase_function: Arc::new(HenyeyGreenstein::new(albedo, settings.g.unwrap_or(0.0))),
+        })
+    }
+
+    // The fraction of light that gets through `distance` of fog. Like rays that escape the scene,
+    // light from infinitely far away only comes through `max_distance` of it.
+    pub fn transmittance(&self, distance: f64) -> f64 {
+        let distance = if distance.is_finite() {
+            distance
+        } else {
+            self.max_distance
+        };
+        (-self.density * distance).exp()
+    }
+
+    // Whether `r` hits a fog particle before `t_hit` (its nearest surface, if there is one), and
+    // if so the attenuation and the scattered ray.
+    pub fn scatter(&self, r: &Ray, t_hit: Option<f64>) -> Option<(Color, Ray)> {
+        let t_max = t_hit.unwrap_or(self.max_distance / r.direction().length());
+        let t = free_flight(r, self.density);
+        if t >= t_max {
+            return None;
+        }
+        let rec = medium_record(r, t, self.phase_function.clone());
+        let srec = self.phase_function.scatter(r, &rec)?;
+        Some((srec.attenuation, srec.scattered))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sphere::Sphere;
+
+    #[test]
+    fn henyey_greenstein_scatters_forwards_on_average() {
+        // The mean cosine of the scattering angle is g.
+        let white = Arc::new(SolidColor::new(Color::new(1.0, 1.0, 1.0)));
+        let incoming = Vec3::new(0.0, 0.0, 2.0);
+        for g in [-0.5, 0.0, 0.7] {
+            let phase = HenyeyGreenstein::new(white.clone(), g);
+            let n = 20000;
+            let mean: f64 = (0..n)
+                .map(|_| phase.sample_direction(incoming).z())
+                .sum::<f64>()
+                / n as f64;
+            assert!((mean - g).abs() < 0.03);
+        }
+    }
+
+    #[test]
+    fn media_let_light_through_by_density() {
+        let white = Arc::new(SolidColor::new(Color::new(1.0, 1.0, 1.0)));
+        let boundary = Box::new(Sphere::new(
+            Point3::new(0.0, 0.0, 0.0),
+            1.0,
+            Arc::new(HenyeyGreenstein::isotropic(white.clone())),
+        ));
+        let medium =
+            ConstantMedium::new(boundary, 0.5, Arc::new(HenyeyGreenstein::isotropic(white)));
+
+        // Straight through the middle is 2 units of smoke, which lets exp(-1) of rays through.
+        let r = Ray::new(Point3::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+        let n = 20000;
+        let through = (0..n)
+            .filter(|_| medium.hit(&r, 0.001, f64::INFINITY).is_none())
+            .count();
+        assert!((through as f64 / n as f64 - (-1.0f64).exp()).abs() < 0.02);
+        let transmittance = medium.transmittance(&r, 0.001, f64::INFINITY);
+        assert!((transmittance - (-1.0f64).exp()).abs() < 1.0e-6);
+    }
+
+    #[test]
+    fn delta_and_ratio_tracking_agree() {
+        // A grid thickening from 0 to 2 along z, over the unit cube: straight through along z
+        // there's 1 unit of smoke on average, which lets exp(-1) of rays through.
+        let white = Arc::new(SolidColor::new(Color::new(1.0, 1.0, 1.0)));
+        let grid = DensityGrid::new([1, 1, 2], vec![0.5, 1.5]);
+        let bounds = Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0));
+        let medium = GridMedium::new(
+            grid,
+            bounds,
+            1.0,
+            Arc::new(HenyeyGreenstein::isotropic(white)),
+        );
+
+        let r = Ray::new(Point3::new(0.5, 0.5, -1.0), Vec3::new(0.0, 0.0, 1.0));
+        let n = 20000;
+        let through = (0..n)
+            .filter(|_| medium.hit(&r, 0.001, f64::INFINITY).is_none())
+            .count();
+        assert!((through as f64 / n as f64 - (-1.0f64).exp()).abs() < 0.02);
+        let ratio = (0..n)
+            .map(|_| medium.transmittance(&r, 0.001, f64::INFINITY))
+            .sum::<f64>()
+            / n as f64;
+        assert!((ratio - (-1.0f64).exp()).abs() < 0.01);
+    }
+
+    #[test]
+    fn fog_density_cannot_be_negative() {
+        for density in [-0.5, f64::INFINITY, f64::NAN] {
+            let settings = FogSettings {
+                density,
+                albedo: None,
+                g: None,
+                max_distance: None,
+            };
+            assert!(Fog::new(&settings).is_err());
+        }
+    }
+}
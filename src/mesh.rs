@@ -0,0 +1,191 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::Aabb,
+    bvh::Bvh,
+    hit::{Hit, HitRecord, World},
+    material::Scatter,
+    ray::Ray,
+    triangle,
+    vec::{Point3, Vec3},
+};
+
+// Vertex and index buffers shared by every triangle of a mesh. Each entry of `indices` names the
+// three vertices of one face, and indexes `positions` and, when present, `normals` and `uvs`.
+pub struct MeshData {
+    positions: Vec<Point3>,
+    indices: Vec<[usize; 3]>,
+    normals: Option<Vec<Vec3>>,
+    uvs: Option<Vec<[f64; 2]>>,
+}
+
+impl MeshData {
+    pub fn new(
+        positions: Vec<Point3>,
+        indices: Vec<[usize; 3]>,
+        normals: Option<Vec<Vec3>>,
+        uvs: Option<Vec<[f64; 2]>>,
+    ) -> MeshData {
+        assert!(
+            indices.iter().flatten().all(|&i| i < positions.len()),
+            "Every mesh index needs to refer to an existing vertex!"
+        );
+        if let Some(normals) = &normals {
+            assert!(
+                normals.len() == positions.len(),
+                "A mesh needs either no normals or one normal per vertex!"
+            );
+        }
+        if let Some(uvs) = &uvs {
+            assert!(
+                uvs.len() == positions.len(),
+                "A mesh needs either no texture coordinates or one pair per vertex!"
+            );
+        }
+
+        MeshData {
+            positions,
+            indices,
+            normals,
+            uvs,
+        }
+    }
+
+    fn vertices(&self, face: usize) -> (Point3, Point3, Point3) {
+        let [i0, i1, i2] = self.indices[face];
+        (self.positions[i0], self.positions[i1], self.positions[i2])
+    }
+}
+
+// One face of a TriangleMesh. These only live inside the mesh's own Bvh.
+struct MeshTriangle {
+    mesh: Arc<MeshData>,
+    face: usize,
+    mat: Arc<dyn Scatter>,
+}
+
+impl Hit for MeshTriangle {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let (p0, p1, p2) = self.mesh.vertices(self.face);
+        let (t, b1, b2) = triangle::intersect(p0, p1, p2, r, t_min, t_max)?;
+
+        // As for a lone Triangle, the barycentric coordinates stand in for missing texture
+        // coordinates.
+        let mut rec = HitRecord {
+            t,
+            p: r.at(t),
+            mat: self.mat.clone(),
+            normal: Vec3::new(0.0, 0.0, 0.0),
+            shading_normal: Vec3::new(0.0, 0.0, 0.0),
+            u: b1,
+            v: b2,
+            dpdu: p1 - p0,
+            dpdv: p2 - p0,
+            front_face: false,
+        };
+        let outward_normal = (p1 - p0).cross(p2 - p0).normalized();
+        rec.set_face_normal(r, outward_normal);
+
+        let [i0, i1, i2] = self.mesh.indices[self.face];
+        let b0 = 1.0 - b1 - b2;
+        if let Some(uvs) = &self.mesh.uvs {
+            rec.u = b0 * uvs[i0][0] + b1 * uvs[i1][0] + b2 * uvs[i2][0];
+            rec.v = b0 * uvs[i0][1] + b1 * uvs[i1][1] + b2 * uvs[i2][1];
+
+            // Solve p_i - p2 = (u_i - u2) dp/du + (v_i - v2) dp/dv for i = 0, 1. When the texture
+            // coordinates don't span the face, any frame around the normal will do.
+            let (du02, dv02) = (uvs[i0][0] - uvs[i2][0], uvs[i0][1] - uvs[i2][1]);
+            let (du12, dv12) = (uvs[i1][0] - uvs[i2][0], uvs[i1][1] - uvs[i2][1]);
+            let (dp02, dp12) = (p0 - p2, p1 - p2);
+            let determinant = du02 * dv12 - dv02 * du12;
+            if determinant.abs() > 1.0e-12 {
+                rec.dpdu = (dv12 * dp02 - dv02 * dp12) / determinant;
+                rec.dpdv = (du02 * dp12 - du12 * dp02) / determinant;
+            } else {
+                (rec.dpdu, rec.dpdv) = outward_normal.orthonormal_basis();
+            }
+        }
+
+        // With per-vertex normals, shade smoothly by interpolating them across the face. Which side
+        // was hit is still decided by the flat face normal above.
+        if let Some(normals) = &self.mesh.normals {
+            let n = (b0 * normals[i0] + b1 * normals[i1] + b2 * normals[i2]).normalized();
+            rec.set_shading_normal(n);
+        }
+
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let (p0, p1, p2) = self.mesh.vertices(self.face);
+        Some(triangle::bounding_box(p0, p1, p2))
+    }
+}
+
+// A mesh of triangles sharing one set of vertex buffers and one material. The faces are kept in a
+// Bvh of their own, so the scene's Bvh only sees the mesh as a single object.
+pub struct TriangleMesh {
+    triangles: Bvh,
+}
+
+impl TriangleMesh {
+    pub fn new(mesh: Arc<MeshData>, mat: Arc<dyn Scatter>) -> TriangleMesh {
+        let mut triangles = World::new();
+        for face in 0..mesh.indices.len() {
+            triangles.push(Box::new(MeshTriangle {
+                mesh: mesh.clone(),
+                face,
+                mat: mat.clone(),
+            }));
+        }
+
+        TriangleMesh {
+            triangles: Bvh::new(triangles),
+        }
+    }
+}
+
+impl Hit for TriangleMesh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.triangles.hit(r, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.triangles.bounding_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::vec::Color;
+
+    #[test]
+    fn mesh_normals_are_interpolated() {
+        let mat = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        // A unit square in the z = 0 plane whose vertex normals all lean towards +x.
+        let lean = Vec3::new(1.0, 0.0, 1.0).normalized();
+        let mesh = Arc::new(MeshData::new(
+            vec![
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(1.0, 0.0, 0.0),
+                Point3::new(1.0, 1.0, 0.0),
+                Point3::new(0.0, 1.0, 0.0),
+            ],
+            vec![[0, 1, 2], [0, 2, 3]],
+            Some(vec![lean; 4]),
+            None,
+        ));
+        let square = TriangleMesh::new(mesh, mat);
+
+        for (x, y) in [(0.75, 0.25), (0.25, 0.75)] {
+            let r = Ray::new(Point3::new(x, y, 1.0), Vec3::new(0.0, 0.0, -1.0));
+            let rec = square.hit(&r, 0.001, f64::INFINITY).unwrap();
+            assert_eq!(rec.t, 1.0);
+            assert!((rec.shading_normal - lean).near_zero());
+            // The geometric normal stays flat.
+            assert!((rec.normal - Vec3::new(0.0, 0.0, 1.0)).near_zero());
+        }
+    }
+}
@@ -0,0 +1,382 @@
+use std::f64::consts::PI;
+
+use rand::Rng;
+use serde::Deserialize;
+
+use super::hit::HitRecord;
+use super::material::{Scatter, ScatterRecord};
+use super::ray::Ray;
+use super::vec::{Color, Vec3};
+
+// An orthonormal frame around a normal, for working in the local coordinates the microfacet
+// formulas are written in: z along the normal.
+pub struct Frame {
+    t: Vec3,
+    b: Vec3,
+    n: Vec3,
+}
+
+impl Frame {
+    pub fn new(n: Vec3) -> Frame {
+        let (t, b) = n.orthonormal_basis();
+        Frame { t, b, n }
+    }
+
+    pub fn to_local(&self, v: Vec3) -> Vec3 {
+        Vec3::new(v.dot(self.t), v.dot(self.b), v.dot(self.n))
+    }
+
+    pub fn to_world(&self, v: Vec3) -> Vec3 {
+        v.x() * self.t + v.y() * self.b + v.z() * self.n
+    }
+}
+
+// GGX (Trowbridge-Reitz) roughness. Artists' roughness in [0, 1] is squared, which makes it
+// look more linear; very small values are kept away from a degenerate, perfectly sharp lobe.
+pub fn alpha_from_roughness(roughness: f64) -> f64 {
+    roughness.clamp(0.0, 1.0).powi(2).max(1.0e-4)
+}
+
+// Smith's Lambda for GGX, from which the masking and shadowing terms follow.
+fn smith_lambda(w: Vec3, alpha: f64) -> f64 {
+    let cos2 = w.z() * w.z();
+    if cos2 <= 0.0 {
+        return f64::INFINITY;
+    }
+    let tan2 = (1.0 - cos2).max(0.0) / cos2;
+    ((1.0 + alpha * alpha * tan2).sqrt() - 1.0) / 2.0
+}
+
+// The fraction of microfacets facing w that w actually sees.
+pub fn smith_g1(w: Vec3, alpha: f64) -> f64 {
+    1.0 / (1.0 + smith_lambda(w, alpha))
+}
+
+// Height-correlated masking and shadowing for the pair of directions wo and wi.
+pub fn smith_g2(wo: Vec3, wi: Vec3, alpha: f64) -> f64 {
+    1.0 / (1.0 + smith_lambda(wo, alpha) + smith_lambda(wi, alpha))
+}
+
+// The GGX distribution of microfacet normals, for m in local coordinates.
+pub fn ggx_d(m: Vec3, alpha: f64) -> f64 {
+    if m.z() <= 0.0 {
+        return 0.0;
+    }
+    let a2 = alpha * alpha;
+    let t = (m.x() * m.x() + m.y() * m.y()) / a2 + m.z() * m.z();
+    1.0 / (PI * a2 * t * t)
+}
+
+// For light reflected from wi into wo (local, both above the surface) by the microfacet halfway
+// between them: that half vector, the BSDF times the cosine bar the Fresnel term, D G2 / (4 cos_o),
+// and the density with which `sample_visible_normal` and a reflection pick wi, which is
+// D_v(m) / (4 wo.m) = G1 D / (4 cos_o).
+pub fn ggx_reflection(wo: Vec3, wi: Vec3, alpha: f64) -> Option<(Vec3, f64, f64)> {
+    if wo.z() <= 0.0 || wi.z() <= 0.0 {
+        return None;
+    }
+    let h = wo + wi;
+    if h.near_zero() {
+        return None;
+    }
+    let h = h.normalized();
+    let d = ggx_d(h, alpha);
+    Some((
+        h,
+        d * smith_g2(wo, wi, alpha) / (4.0 * wo.z()),
+        d * smith_g1(wo, alpha) / (4.0 * wo.z()),
+    ))
+}
+
+// Samples a microfacet normal from the GGX normals visible from wo (local, with wo.z > 0), so
+// that no samples are wasted on facets wo can't see. From Heitz, "Sampling the GGX Distribution
+// of Visible Normals" (2018).
+pub fn sample_visible_normal(wo: Vec3, alpha: f64, u1: f64, u2: f64) -> Vec3 {
+    // Stretch wo into the configuration where the distribution is a hemisphere.
+    let vh = Vec3::new(alpha * wo.x(), alpha * wo.y(), wo.z()).normalized();
+    let lensq = vh.x() * vh.x() + vh.y() * vh.y();
+    let t1 = if lensq > 0.0 {
+        Vec3::new(-vh.y(), vh.x(), 0.0) / lensq.sqrt()
+    } else {
+        Vec3::new(1.0, 0.0, 0.0)
+    };
+    let t2 = vh.cross(t1);
+
+    // A point on the projected disk, squeezed onto the half that's visible.
+    let r = u1.sqrt();
+    let phi = 2.0 * PI * u2;
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + vh.z());
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).max(0.0).sqrt() + s * r * phi.sin();
+    let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+
+    Vec3::new(alpha * nh.x(), alpha * nh.y(), nh.z().max(0.0)).normalized()
+}
+
+// Unpolarised Fresnel reflectance of a dielectric boundary, where `eta` is the ratio of the
+// indices of refraction on the incident side over the far side. 1 means total internal reflection.
+pub fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let rs = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let rp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (rs * rs + rp * rp) / 2.0
+}
+
+// Unpolarised Fresnel reflectance of a conductor with complex index of refraction eta + ik, per
+// colour channel, seen from air. The exact form, as in PBRT.
+pub fn fresnel_conductor(cos_i: f64, eta: Color, k: Color) -> Color {
+    let cos2 = cos_i.clamp(0.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+    let channel = |eta: f64, k: f64| {
+        let t0 = eta * eta - k * k - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+        let t1 = a2_plus_b2 + cos2;
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+        let t2 = 2.0 * cos_i * a;
+        let rs = (t1 - t2) / (t1 + t2);
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+        0.5 * (rp + rs)
+    };
+    Color::new(
+        channel(eta.x(), k.x()),
+        channel(eta.y(), k.y()),
+        channel(eta.z(), k.z()),
+    )
+}
+
+// Measured complex indices of refraction, at roughly the red, green and blue wavelengths.
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Conductor {
+    Gold,
+    Copper,
+    Aluminium,
+    Silver,
+}
+
+impl Conductor {
+    // (eta, k)
+    pub fn ior(self) -> (Color, Color) {
+        match self {
+            Conductor::Gold => (
+                Color::new(0.143, 0.374, 1.442),
+                Color::new(3.983, 2.385, 1.603),
+            ),
+            Conductor::Copper => (
+                Color::new(0.200, 0.924, 1.102),
+                Color::new(3.912, 2.452, 2.142),
+            ),
+            Conductor::Aluminium => (
+                Color::new(1.657, 0.880, 0.521),
+                Color::new(9.224, 6.270, 4.837),
+            ),
+            Conductor::Silver => (
+                Color::new(0.155, 0.117, 0.138),
+                Color::new(4.828, 3.122, 2.147),
+            ),
+        }
+    }
+}
+
+// A rough metal: GGX microfacets, each a perfect mirror with the Fresnel reflectance of the metal.
+pub struct RoughConductor {
+    eta: Color,
+    k: Color,
+    alpha: f64,
+}
+
+impl RoughConductor {
+    pub fn new(eta: Color, k: Color, roughness: f64) -> RoughConductor {
+        RoughConductor {
+            eta,
+            k,
+            alpha: alpha_from_roughness(roughness),
+        }
+    }
+}
+
+impl Scatter for RoughConductor {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let frame = Frame::new(rec.shading_normal);
+        let wo = frame.to_local(-1.0 * r_in.direction().normalized());
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        let mut rng = rand::thread_rng();
+        let m = sample_visible_normal(wo, self.alpha, rng.gen(), rng.gen());
+        let wi = (-1.0 * wo).reflect(m);
+        if wi.z() <= 0.0 {
+            return None;
+        }
+
+        // With visible normals sampled, the weight f cos / pdf comes down to F G2 / G1.
+        let weight = smith_g2(wo, wi, self.alpha) / smith_g1(wo, self.alpha);
+        let attenuation = weight * fresnel_conductor(wo.dot(m), self.eta, self.k);
+        let direction = frame.to_world(wi);
+        Some(ScatterRecord {
+            attenuation,
+            scattered: Ray::new(rec.p, direction),
+            pdf: Some(self.pdf(r_in, rec, direction)),
+        })
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Color {
+        let frame = Frame::new(rec.shading_normal);
+        let wo = frame.to_local(-1.0 * r_in.direction().normalized());
+        let wi = frame.to_local(direction.normalized());
+        match ggx_reflection(wo, wi, self.alpha) {
+            Some((h, f, _)) if direction.dot(rec.normal) > 0.0 => {
+                f * fresnel_conductor(wo.dot(h), self.eta, self.k)
+            }
+            _ => Color::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+        let frame = Frame::new(rec.shading_normal);
+        let wo = frame.to_local(-1.0 * r_in.direction().normalized());
+        let wi = frame.to_local(direction.normalized());
+        ggx_reflection(wo, wi, self.alpha).map_or(0.0, |(_, _, pdf)| pdf)
+    }
+}
+
+// Rough glass: GGX microfacets that each reflect or refract like Dielectric does.
+pub struct RoughDielectric {
+    // index of refraction
+    ir: f64,
+    alpha: f64,
+}
+
+impl RoughDielectric {
+    pub fn new(ir: f64, roughness: f64) -> RoughDielectric {
+        RoughDielectric {
+            ir,
+            alpha: alpha_from_roughness(roughness),
+        }
+    }
+
+    // The ratio of the indices of refraction. The shading normal faces the side the ray came
+    // from, so we're going from the outside in exactly when the front face was hit.
+    fn eta(&self, rec: &HitRecord) -> f64 {
+        if rec.front_face {
+            1.0 / self.ir
+        } else {
+            self.ir
+        }
+    }
+}
+
+impl Scatter for RoughDielectric {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let eta = self.eta(rec);
+        let frame = Frame::new(rec.shading_normal);
+        let wo = frame.to_local(-1.0 * r_in.direction().normalized());
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        let mut rng = rand::thread_rng();
+        let m = sample_visible_normal(wo, self.alpha, rng.gen(), rng.gen());
+        let cos_o = wo.dot(m);
+
+        // Choosing between reflection and refraction with the Fresnel probability cancels
+        // Fresnel out of the weight. Only reflections come with a density; refractions are
+        // left to the scattered ray.
+        let (wi, reflected) = if rng.gen::<f64>() < fresnel_dielectric(cos_o, eta) {
+            let wi = (-1.0 * wo).reflect(m);
+            if wi.z() <= 0.0 {
+                return None;
+            }
+            (wi, true)
+        } else {
+            let cos_t = (1.0 - eta * eta * (1.0 - cos_o * cos_o)).sqrt();
+            let wi = (eta * cos_o - cos_t) * m - eta * wo;
+            if wi.z() >= 0.0 {
+                return None;
+            }
+            (wi, false)
+        };
+
+        let weight = smith_g2(wo, wi, self.alpha) / smith_g1(wo, self.alpha);
+        let direction = frame.to_world(wi);
+        Some(ScatterRecord {
+            attenuation: Color::new(weight, weight, weight),
+            scattered: Ray::new(rec.p, direction),
+            pdf: reflected.then(|| self.pdf(r_in, rec, direction)),
+        })
+    }
+
+    // Only the reflection.
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Color {
+        let frame = Frame::new(rec.shading_normal);
+        let wo = frame.to_local(-1.0 * r_in.direction().normalized());
+        let wi = frame.to_local(direction.normalized());
+        match ggx_reflection(wo, wi, self.alpha) {
+            Some((h, f, _)) if direction.dot(rec.normal) > 0.0 => {
+                let fresnel = fresnel_dielectric(wo.dot(h), self.eta(rec));
+                Color::new(1.0, 1.0, 1.0) * (fresnel * f)
+            }
+            _ => Color::new(0.0, 0.0, 0.0),
+        }
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+        let frame = Frame::new(rec.shading_normal);
+        let wo = frame.to_local(-1.0 * r_in.direction().normalized());
+        let wi = frame.to_local(direction.normalized());
+        ggx_reflection(wo, wi, self.alpha).map_or(0.0, |(h, _, pdf)| {
+            fresnel_dielectric(wo.dot(h), self.eta(rec)) * pdf
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::tests::assert_density_agrees_with_sampling;
+
+    #[test]
+    fn visible_normals_face_the_viewer() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..1000 {
+            let wo = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0.05);
+            let wo = wo.normalized();
+            let m = sample_visible_normal(wo, 0.5, rng.gen(), rng.gen());
+            assert!((m.length() - 1.0).abs() < 1.0e-9);
+            assert!(m.z() >= 0.0);
+            assert!(m.dot(wo) >= -1.0e-9);
+        }
+    }
+
+    #[test]
+    fn fresnel_matches_normal_incidence() {
+        // Head on, the reflectance is ((n - 1)^2 + k^2) / ((n + 1)^2 + k^2).
+        let (eta, k) = Conductor::Gold.ior();
+        let f = fresnel_conductor(1.0, eta, k);
+        for i in 0..3 {
+            let expected =
+                ((eta[i] - 1.0).powi(2) + k[i] * k[i]) / ((eta[i] + 1.0).powi(2) + k[i] * k[i]);
+            assert!((f[i] - expected).abs() < 1.0e-9);
+        }
+
+        // And with no absorption a conductor is just a dielectric.
+        let glass = fresnel_conductor(0.6, Color::new(1.5, 1.5, 1.5), Color::new(0.0, 0.0, 0.0));
+        assert!((glass.x() - fresnel_dielectric(0.6, 1.0 / 1.5)).abs() < 1.0e-9);
+    }
+
+    #[test]
+    fn rough_samples_agree_with_their_density() {
+        let (eta, k) = Conductor::Gold.ior();
+        assert_density_agrees_with_sampling(&RoughConductor::new(eta, k, 0.5));
+        // Only the reflections of rough glass have a density.
+        assert_density_agrees_with_sampling(&RoughDielectric::new(1.5, 0.5));
+    }
+}
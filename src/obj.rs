@@ -0,0 +1,498 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::{
+    material::{Dielectric, Ior, Lambertian, Metal, Scatter},
+    mesh::MeshData,
+    vec::{Color, Point3, Vec3},
+};
+
+// Something that went wrong while reading a Wavefront OBJ or MTL file. Parse errors point at the
+// offending line (counting from 1).
+#[derive(Debug)]
+pub enum ObjError {
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            ObjError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+        }
+    }
+}
+
+impl Error for ObjError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ObjError::Io { source, .. } => Some(source),
+            ObjError::Parse { .. } => None,
+        }
+    }
+}
+
+// The parts of an MTL material we know how to map onto our own materials.
+pub struct MtlMaterial {
+    // Kd
+    diffuse: Color,
+    // Ks
+    specular: Color,
+    // Ns, the Phong exponent
+    shininess: f64,
+    // Ni
+    ior: f64,
+    // d, or 1 - Tr
+    dissolve: f64,
+}
+
+impl MtlMaterial {
+    fn new() -> MtlMaterial {
+        MtlMaterial {
+            diffuse: Color::new(0.8, 0.8, 0.8),
+            specular: Color::new(0.0, 0.0, 0.0),
+            shininess: 0.0,
+            ior: 1.5,
+            dissolve: 1.0,
+        }
+    }
+
+    // Transparent materials become glass, materials that are more specular than diffuse become
+    // metal (rougher the lower their Phong exponent), and everything else is Lambertian.
+    pub fn to_scatter(&self) -> Arc<dyn Scatter> {
+        let max = |c: Color| c.x().max(c.y()).max(c.z());
+
+        if self.dissolve < 1.0 {
+            Arc::new(Dielectric::new(Ior::Constant(self.ior)))
+        } else if max(self.specular) > max(self.diffuse) {
+            let fuzz = (2.0 / (self.shininess + 2.0)).sqrt().min(1.0);
+            Arc::new(Metal::new(self.specular, fuzz))
+        } else {
+            Arc::new(Lambertian::new(self.diffuse))
+        }
+    }
+}
+
+// The faces of one OBJ group that share a material, as a mesh with its own vertex buffers.
+pub struct ObjGroup {
+    pub material: Option<String>,
+    pub mesh: MeshData,
+}
+
+pub struct ObjFile {
+    pub groups: Vec<ObjGroup>,
+    pub materials: HashMap<String, MtlMaterial>,
+}
+
+// Reads an OBJ file and every MTL library it references (relative to the OBJ file).
+pub fn load_obj(path: &Path) -> Result<ObjFile, ObjError> {
+    let text = read_to_string(path)?;
+    let (groups, mtllibs) = parse_obj(&text, path)?;
+
+    let mut materials = HashMap::new();
+    for mtllib in mtllibs {
+        let mtl_path = path.parent().unwrap_or(Path::new("")).join(mtllib);
+        let mtl_text = read_to_string(&mtl_path)?;
+        materials.extend(parse_mtl(&mtl_text, &mtl_path)?);
+    }
+
+    for group in &groups {
+        if let Some((name, line)) = &group.material {
+            if !materials.contains_key(name) {
+                return Err(ObjError::Parse {
+                    path: path.to_path_buf(),
+                    line: *line,
+                    message: format!("unknown material `{}`", name),
+                });
+            }
+        }
+    }
+
+    Ok(ObjFile {
+        groups: groups
+            .into_iter()
+            .map(|group| ObjGroup {
+                material: group.material.map(|(name, _)| name),
+                mesh: group.mesh,
+            })
+            .collect(),
+        materials,
+    })
+}
+
+fn read_to_string(path: &Path) -> Result<String, ObjError> {
+    fs::read_to_string(path).map_err(|source| ObjError::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+// Collects the faces of one (group, material) pair, de-duplicating the OBJ's separate
+// position/texture/normal index triples into the single index per vertex that MeshData uses.
+struct GroupBuilder {
+    // The material name along with the line of the `usemtl` that selected it.
+    material: Option<(String, usize)>,
+    vertex_ids: HashMap<(usize, Option<usize>, Option<usize>), usize>,
+    positions: Vec<Point3>,
+    normals: Vec<Option<Vec3>>,
+    uvs: Vec<Option<[f64; 2]>>,
+    indices: Vec<[usize; 3]>,
+}
+
+impl GroupBuilder {
+    fn new(material: Option<(String, usize)>) -> GroupBuilder {
+        GroupBuilder {
+            material,
+            vertex_ids: HashMap::new(),
+            positions: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            indices: Vec::new(),
+        }
+    }
+
+    fn vertex(
+        &mut self,
+        key: (usize, Option<usize>, Option<usize>),
+        positions: &[Point3],
+        uvs: &[[f64; 2]],
+        normals: &[Vec3],
+    ) -> usize {
+        if let Some(&id) = self.vertex_ids.get(&key) {
+            return id;
+        }
+        let (v, vt, vn) = key;
+        let id = self.positions.len();
+        self.positions.push(positions[v]);
+        self.uvs.push(vt.map(|vt| uvs[vt]));
+        self.normals.push(vn.map(|vn| normals[vn]));
+        self.vertex_ids.insert(key, id);
+        id
+    }
+
+    // Normals and texture coordinates are only kept if every vertex of the group has them.
+    fn finish(self) -> ParsedGroup {
+        let normals = self.normals.into_iter().collect::<Option<Vec<_>>>();
+        let uvs = self.uvs.into_iter().collect::<Option<Vec<_>>>();
+        ParsedGroup {
+            material: self.material,
+            mesh: MeshData::new(self.positions, self.indices, normals, uvs),
+        }
+    }
+}
+
+struct ParsedGroup {
+    material: Option<(String, usize)>,
+    mesh: MeshData,
+}
+
+fn parse_floats<const N: usize>(
+    args: &[&str],
+    path: &Path,
+    line: usize,
+    what: &str,
+) -> Result<[f64; N], ObjError> {
+    let error = |message: String| ObjError::Parse {
+        path: path.to_path_buf(),
+        line,
+        message,
+    };
+    if args.len() < N {
+        return Err(error(format!(
+            "expected {} numbers for {}, found {}",
+            N,
+            what,
+            args.len()
+        )));
+    }
+    let mut out = [0.0; N];
+    for (value, arg) in out.iter_mut().zip(args) {
+        *value = arg
+            .parse()
+            .map_err(|_| error(format!("`{}` is not a number", arg)))?;
+    }
+    Ok(out)
+}
+
+// Turns a 1-based (or, if negative, relative to the end) OBJ index into a 0-based one.
+fn resolve_index(
+    token: &str,
+    count: usize,
+    path: &Path,
+    line: usize,
+    what: &str,
+) -> Result<usize, ObjError> {
+    let error = |message: String| ObjError::Parse {
+        path: path.to_path_buf(),
+        line,
+        message,
+    };
+    let index: i64 = token
+        .parse()
+        .map_err(|_| error(format!("`{}` is not a valid {} index", token, what)))?;
+    let resolved = if index > 0 {
+        index - 1
+    } else {
+        count as i64 + index
+    };
+    if index == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(error(format!(
+            "{} index {} is out of range (there are {} so far)",
+            what, index, count
+        )));
+    }
+    Ok(resolved as usize)
+}
+
+fn parse_obj(text: &str, path: &Path) -> Result<(Vec<ParsedGroup>, Vec<String>), ObjError> {
+    let mut positions = Vec::new();
+    let mut uvs = Vec::new();
+    let mut normals = Vec::new();
+    let mut mtllibs = Vec::new();
+
+    let mut group_name = String::new();
+    let mut material: Option<(String, usize)> = None;
+    let mut builders: Vec<GroupBuilder> = Vec::new();
+    let mut builder_ids: HashMap<(String, Option<String>), usize> = HashMap::new();
+
+    for (i, raw_line) in text.lines().enumerate() {
+        let line = i + 1;
+        let content = raw_line.split('#').next().unwrap_or("");
+        let mut tokens = content.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => {
+                let [x, y, z] = parse_floats(&args, path, line, "a vertex")?;
+                positions.push(Point3::new(x, y, z));
+            }
+            "vt" => {
+                // The second coordinate is optional for 1D textures.
+                let [u] = parse_floats(&args, path, line, "a texture coordinate")?;
+                let v = match args.get(1) {
+                    Some(_) => parse_floats::<2>(&args, path, line, "a texture coordinate")?[1],
+                    None => 0.0,
+                };
+                uvs.push([u, v]);
+            }
+            "vn" => {
+                let [x, y, z] = parse_floats(&args, path, line, "a normal")?;
+                normals.push(Vec3::new(x, y, z).normalized());
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(ObjError::Parse {
+                        path: path.to_path_buf(),
+                        line,
+                        message: format!("a face needs at least 3 vertices, found {}", args.len()),
+                    });
+                }
+
+                let key = (
+                    group_name.clone(),
+                    material.as_ref().map(|(name, _)| name.clone()),
+                );
+                let id = *builder_ids.entry(key).or_insert_with(|| {
+                    builders.push(GroupBuilder::new(material.clone()));
+                    builders.len() - 1
+                });
+                let builder = &mut builders[id];
+
+                let mut face = Vec::with_capacity(args.len());
+                for arg in &args {
+                    let mut parts = arg.split('/');
+                    let v = resolve_index(
+                        parts.next().unwrap_or(""),
+                        positions.len(),
+                        path,
+                        line,
+                        "vertex",
+                    )?;
+                    let vt = match parts.next() {
+                        Some("") | None => None,
+                        Some(vt) => Some(resolve_index(
+                            vt,
+                            uvs.len(),
+                            path,
+                            line,
+                            "texture coordinate",
+                        )?),
+                    };
+                    let vn = match parts.next() {
+                        Some("") | None => None,
+                        Some(vn) => Some(resolve_index(vn, normals.len(), path, line, "normal")?),
+                    };
+                    face.push(builder.vertex((v, vt, vn), &positions, &uvs, &normals));
+                }
+
+                // Polygons are split into a fan of triangles around their first vertex.
+                for k in 1..face.len() - 1 {
+                    builder.indices.push([face[0], face[k], face[k + 1]]);
+                }
+            }
+            "g" | "o" => {
+                group_name = args.join(" ");
+            }
+            "usemtl" => {
+                if args.is_empty() {
+                    return Err(ObjError::Parse {
+                        path: path.to_path_buf(),
+                        line,
+                        message: "`usemtl` needs a material name".to_string(),
+                    });
+                }
+                material = Some((args.join(" "), line));
+            }
+            "mtllib" => {
+                mtllibs.extend(args.iter().map(|arg| arg.to_string()));
+            }
+            // Everything else (smoothing groups, lines, free-form geometry, ...) is ignored.
+            _ => {}
+        }
+    }
+
+    let groups = builders
+        .into_iter()
+        .filter(|builder| !builder.indices.is_empty())
+        .map(GroupBuilder::finish)
+        .collect();
+    Ok((groups, mtllibs))
+}
+
+fn parse_mtl(text: &str, path: &Path) -> Result<HashMap<String, MtlMaterial>, ObjError> {
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlMaterial)> = None;
+
+    for (i, raw_line) in text.lines().enumerate() {
+        let line = i + 1;
+        let content = raw_line.split('#').next().unwrap_or("");
+        let mut tokens = content.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            if args.is_empty() {
+                return Err(ObjError::Parse {
+                    path: path.to_path_buf(),
+                    line,
+                    message: "`newmtl` needs a material name".to_string(),
+                });
+            }
+            if let Some((name, material)) = current.take() {
+                materials.insert(name, material);
+            }
+            current = Some((args.join(" "), MtlMaterial::new()));
+            continue;
+        }
+
+        let material = match &mut current {
+            Some((_, material)) => material,
+            None => {
+                return Err(ObjError::Parse {
+                    path: path.to_path_buf(),
+                    line,
+                    message: format!("`{}` appears before any `newmtl`", keyword),
+                })
+            }
+        };
+        match keyword {
+            "Kd" => {
+                let [r, g, b] = parse_floats(&args, path, line, "Kd")?;
+                material.diffuse = Color::new(r, g, b);
+            }
+            "Ks" => {
+                let [r, g, b] = parse_floats(&args, path, line, "Ks")?;
+                material.specular = Color::new(r, g, b);
+            }
+            "Ns" => {
+                let [ns] = parse_floats(&args, path, line, "Ns")?;
+                material.shininess = ns;
+            }
+            "Ni" => {
+                let [ni] = parse_floats(&args, path, line, "Ni")?;
+                material.ior = ni;
+            }
+            "d" => {
+                let [d] = parse_floats(&args, path, line, "d")?;
+                material.dissolve = d;
+            }
+            "Tr" => {
+                let [tr] = parse_floats(&args, path, line, "Tr")?;
+                material.dissolve = 1.0 - tr;
+            }
+            // Everything else (ambient colour, illumination model, texture maps, ...) is ignored.
+            _ => {}
+        }
+    }
+    if let Some((name, material)) = current {
+        materials.insert(name, material);
+    }
+
+    Ok(materials)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn obj_faces_are_triangulated_and_grouped() {
+        let text = "\
+mtllib box.mtl
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vn 0 0 1
+g front
+usemtl red
+f 1//1 2//1 3//1 4//1
+g back
+usemtl blue
+f -4 -2 -3
+";
+        let (groups, mtllibs) = parse_obj(text, Path::new("box.obj")).unwrap();
+        assert_eq!(mtllibs, vec!["box.mtl".to_string()]);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].material.as_ref().unwrap().0, "red");
+        assert_eq!(groups[1].material.as_ref().unwrap().0, "blue");
+    }
+
+    #[test]
+    fn obj_errors_report_the_line() {
+        let text = "v 0 0 0\nv 1 0 0\nf 1 2 3\n";
+        match parse_obj(text, Path::new("bad.obj")) {
+            Err(ObjError::Parse { line, .. }) => assert_eq!(line, 3),
+            _ => panic!("expected a parse error"),
+        }
+
+        let text = "newmtl shiny\nKs 1 one 1\n";
+        match parse_mtl(text, Path::new("bad.mtl")) {
+            Err(ObjError::Parse { line, .. }) => assert_eq!(line, 2),
+            _ => panic!("expected a parse error"),
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use super::hdr::{write_hdr, HdrImage};
+use super::tonemap::ToneMapper;
+
+// Encoder options. Which encoder is used depends on the output file's extension.
+#[derive(Deserialize, Default)]
+pub struct OutputSettings {
+    // Write .ppm files as ASCII P3 instead of binary P6. P3 is several times the size and slow to
+    // write, so it's only for tools that can't read anything else.
+    ascii_ppm: Option<bool>,
+    // 8 (the default) or 16.
+    png_bit_depth: Option<u8>,
+}
+
+enum Encoder {
+    Stream(StreamEncoder),
+    // The exr crate creates the file itself.
+    OpenExr,
+}
+
+// Encoders that write into a file we open for them.
+enum StreamEncoder {
+    AsciiPpm,
+    BinaryPpm,
+    Png { bit_depth: png::BitDepth },
+    // The float formats, OpenEXR too, get the linear framebuffer as it is, without gamma or
+    // clamping.
+    Pfm,
+    Radiance,
+}
+
+fn unsupported(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn encoder_for(path: &Path, settings: &OutputSettings) -> io::Result<Encoder> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+
+    let encoder = match extension.as_deref() {
+        Some("ppm") if settings.ascii_ppm.unwrap_or(false) => StreamEncoder::AsciiPpm,
+        Some("ppm" | "pnm") => StreamEncoder::BinaryPpm,
+        Some("png") => match settings.png_bit_depth.unwrap_or(8) {
+            8 => StreamEncoder::Png {
+                bit_depth: png::BitDepth::Eight,
+            },
+            16 => StreamEncoder::Png {
+                bit_depth: png::BitDepth::Sixteen,
+            },
+            other => {
+                return Err(unsupported(format!(
+                    "PNG bit depth must be 8 or 16, not {}",
+                    other
+                )))
+            }
+        },
+        Some("pfm") => StreamEncoder::Pfm,
+        Some("hdr") => StreamEncoder::Radiance,
+        Some("exr") => return Ok(Encoder::OpenExr),
+        _ => {
+            return Err(unsupported(format!(
+                "don't know how to write {}, use a .ppm, .pnm, .png, .pfm, .hdr or .exr file",
+                path.display()
+            )))
+        }
+    };
+    Ok(Encoder::Stream(encoder))
+}
+
+// Writes images to one path, in the format given by its extension. Creating one up front means
+// we find out about unsupported formats before spending time on rendering.
+pub struct ImageWriter {
+    path: PathBuf,
+    encoder: Encoder,
+    tone_mapper: ToneMapper,
+}
+
+impl ImageWriter {
+    // `tone_mapper` is only used for the formats that can't hold the linear framebuffer as is.
+    pub fn new(
+        path: &Path,
+        settings: &OutputSettings,
+        tone_mapper: ToneMapper,
+    ) -> io::Result<ImageWriter> {
+        Ok(ImageWriter {
+            path: path.to_path_buf(),
+            encoder: encoder_for(path, settings)?,
+            tone_mapper,
+        })
+    }
+
+    // Writes the linear framebuffer `image`.
+    pub fn write(&self, image: &HdrImage) -> io::Result<()> {
+        match &self.encoder {
+            Encoder::Stream(encoder) => self.write_stream(encoder, image),
+            Encoder::OpenExr => write_exr(&self.path, image),
+        }
+    }
+
+    fn write_stream(&self, encoder: &StreamEncoder, image: &HdrImage) -> io::Result<()> {
+        let mut output = BufWriter::new(File::create(&self.path)?);
+        let display = || DisplayImage {
+            width: image.width,
+            height: image.height,
+            pixels: image
+                .pixels
+                .iter()
+                .map(|&pixel| self.tone_mapper.apply(pixel))
+                .collect(),
+        };
+
+        match *encoder {
+            StreamEncoder::AsciiPpm => write_ascii_ppm(&mut output, &display())?,
+            StreamEncoder::BinaryPpm => write_binary_ppm(&mut output, &display())?,
+            StreamEncoder::Png { bit_depth } => write_png(&mut output, &display(), bit_depth)?,
+            StreamEncoder::Pfm => write_pfm(&mut output, image)?,
+            StreamEncoder::Radiance => write_hdr(&mut output, image)?,
+        }
+        output.flush()
+    }
+}
+
+// The tone mapped framebuffer, with display values nominally in [0, 1].
+struct DisplayImage {
+    width: usize,
+    height: usize,
+    pixels: Vec<[f64; 3]>,
+}
+
+fn to_u8(v: f64) -> u8 {
+    (256.0 * v.clamp(0.0, 0.999)) as u8
+}
+
+fn to_u16(v: f64) -> u16 {
+    (65536.0 * v.clamp(0.0, 0.99999)) as u16
+}
+
+fn write_ascii_ppm(output: &mut impl Write, image: &DisplayImage) -> io::Result<()> {
+    writeln!(output, "P3")?;
+    writeln!(output, "{} {}", image.width, image.height)?;
+    writeln!(output, "255")?;
+    for &pixel in &image.pixels {
+        let [r, g, b] = pixel.map(to_u8);
+        writeln!(output, "{} {} {}", r, g, b)?;
+    }
+    Ok(())
+}
+
+fn write_binary_ppm(output: &mut impl Write, image: &DisplayImage) -> io::Result<()> {
+    write!(output, "P6\n{} {}\n255\n", image.width, image.height)?;
+    for &pixel in &image.pixels {
+        output.write_all(&pixel.map(to_u8))?;
+    }
+    Ok(())
+}
+
+fn write_png(
+    output: &mut impl Write,
+    image: &DisplayImage,
+    bit_depth: png::BitDepth,
+) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(output, image.width as u32, image.height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(bit_depth);
+    let mut writer = encoder.write_header()?;
+
+    let data: Vec<u8> = match bit_depth {
+        png::BitDepth::Sixteen => image
+            .pixels
+            .iter()
+            .flat_map(|pixel| pixel.map(to_u16))
+            .flat_map(u16::to_be_bytes)
+            .collect(),
+        _ => image
+            .pixels
+            .iter()
+            .flat_map(|pixel| pixel.map(to_u8))
+            .collect(),
+    };
+    writer.write_image_data(&data)?;
+    writer.finish()?;
+    Ok(())
+}
+
+// Portable float map: 32-bit little-endian floats, with the bottom row first.
+fn write_pfm(output: &mut impl Write, image: &HdrImage) -> io::Result<()> {
+    // A negative scale marks the data as little-endian.
+    write!(output, "PF\n{} {}\n-1.0\n", image.width, image.height)?;
+    for row in image.pixels.chunks(image.width).rev() {
+        for pixel in row {
+            for i in 0..3 {
+                output.write_all(&(pixel[i] as f32).to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// A single-layer, 32-bit float RGB OpenEXR file.
+fn write_exr(path: &Path, image: &HdrImage) -> io::Result<()> {
+    exr::prelude::write_rgb_file(path, image.width, image.height, |x, y| {
+        let pixel = image.get(x, y);
+        (pixel.x() as f32, pixel.y() as f32, pixel.z() as f32)
+    })
+    .map_err(|e| io::Error::other(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ppm_encoders_agree() {
+        let image = DisplayImage {
+            width: 2,
+            height: 1,
+            pixels: vec![[0.5, 1.0, 0.0], [2.0, 0.0, 0.25]],
+        };
+
+        let mut ascii = Vec::new();
+        write_ascii_ppm(&mut ascii, &image).unwrap();
+        assert_eq!(
+            String::from_utf8(ascii).unwrap(),
+            "P3\n2 1\n255\n128 255 0\n255 0 64\n"
+        );
+
+        let mut binary = Vec::new();
+        write_binary_ppm(&mut binary, &image).unwrap();
+        assert_eq!(binary, b"P6\n2 1\n255\n\x80\xff\x00\xff\x00\x40");
+    }
+
+    #[test]
+    fn ppm_files_are_binary_unless_asked_otherwise() {
+        let ascii: OutputSettings = serde_json::from_str(r#"{"ascii_ppm": true}"#).unwrap();
+        let binary = |path: &str, settings: &OutputSettings| {
+            matches!(
+                encoder_for(Path::new(path), settings).unwrap(),
+                Encoder::Stream(StreamEncoder::BinaryPpm)
+            )
+        };
+        assert!(binary("out.ppm", &OutputSettings::default()));
+        assert!(binary("out.PNM", &OutputSettings::default()));
+        assert!(binary("out.pnm", &ascii));
+        assert!(!binary("out.ppm", &ascii));
+    }
+}
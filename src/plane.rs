@@ -1,6 +1,7 @@
 use std::sync::Arc;
 
 use crate::{
+    aabb::Aabb,
     hit::{Hit, HitRecord},
     material::Scatter,
     ray::Ray,
@@ -18,6 +19,8 @@ pub struct Plane {
     point1: Point3,
     point2: Point3,
     mat: Arc<dyn Scatter>,
+    // The two axes the segment is mostly spread along, which u and v follow.
+    uv_axes: (usize, usize),
 }
 
 impl Plane {
@@ -30,21 +33,61 @@ impl Plane {
             "The two points provided need to fall on the same plane!"
         );
 
+        // Drop the axis the normal points along the most.
+        let n = [normal.x().abs(), normal.y().abs(), normal.z().abs()];
+        let uv_axes = if n[0] >= n[1] && n[0] >= n[2] {
+            (1, 2)
+        } else if n[1] >= n[2] {
+            (2, 0)
+        } else {
+            (0, 1)
+        };
+
         Plane {
             normal,
             point1,
             point2,
             mat,
+            uv_axes,
         }
     }
+
+    // u and v run along the two uv_axes across the segment, and the point moves along the third
+    // axis as needed to stay in the plane.
+    fn tangents(&self) -> (Vec3, Vec3) {
+        let (a, b) = self.uv_axes;
+        let c = 3 - a - b;
+        let along = |axis: usize| {
+            let extent = (self.point2[axis] - self.point1[axis]).abs();
+            // A segment with no width in this direction still gets a unit-length tangent.
+            let extent = if extent > 0.0 { extent } else { 1.0 };
+            let mut tangent = Vec3::new(0.0, 0.0, 0.0);
+            tangent[axis] = extent;
+            tangent[c] = -extent * self.normal[axis] / self.normal[c];
+            tangent
+        };
+        (along(a), along(b))
+    }
 }
 
+// How far outside the box spanned by point1 and point2 a hit may land and still count.
+const SEGMENT_EPS: f64 = 0.01;
+
 fn between(x1: f64, x2: f64, p: f64) -> bool {
-    let eps = 0.01;
     if x1 < x2 {
-        x1 <= p + eps && p - eps <= x2
+        x1 <= p + SEGMENT_EPS && p - SEGMENT_EPS <= x2
     } else {
-        x2 <= p + eps && p - eps <= x1
+        x2 <= p + SEGMENT_EPS && p - SEGMENT_EPS <= x1
+    }
+}
+
+// How far p is along the way from the smaller of x1 and x2 to the larger, in [0, 1].
+fn fraction(x1: f64, x2: f64, p: f64) -> f64 {
+    let (lo, hi) = if x1 < x2 { (x1, x2) } else { (x2, x1) };
+    if hi > lo {
+        ((p - lo) / (hi - lo)).clamp(0.0, 1.0)
+    } else {
+        0.0
     }
 }
 
@@ -75,22 +118,36 @@ impl Hit for Plane {
 
         let p = r.at(root);
 
-        let rec = HitRecord {
+        let (a, b) = self.uv_axes;
+        let (dpdu, dpdv) = self.tangents();
+        let mut rec = HitRecord {
             t: root,
             p,
             mat: self.mat.clone(),
-            normal: self.normal,
+            normal: Vec3::new(0.0, 0.0, 0.0),
+            shading_normal: Vec3::new(0.0, 0.0, 0.0),
+            u: fraction(self.point1[a], self.point2[a], p[a]),
+            v: fraction(self.point1[b], self.point2[b], p[b]),
+            dpdu,
+            dpdv,
             front_face: false,
         };
+        // `normal` is the front face; hits from behind see it flipped.
+        rec.set_face_normal(r, self.normal.normalized());
 
         // We don't want the entire plane, only a plane segment between point1 and point2.
         if between(self.point1.x(), self.point2.x(), p.x())
             && between(self.point1.y(), self.point2.y(), p.y())
             && between(self.point1.z(), self.point2.z(), p.z())
         {
-            return Some(rec);
+            Some(rec)
         } else {
-            return None;
+            None
         }
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        // Pad by a little more than SEGMENT_EPS so the box can't clip hits `between` accepts.
+        Some(Aabb::from_points(self.point1, self.point2).padded(2.0 * SEGMENT_EPS))
+    }
 }
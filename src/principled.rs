@@ -0,0 +1,346 @@
+use std::f64::consts::PI;
+use std::io;
+use std::sync::Arc;
+
+use rand::Rng;
+use serde::Deserialize;
+
+use super::hit::HitRecord;
+use super::material::{Scatter, ScatterRecord};
+use super::microfacet::{
+    alpha_from_roughness, fresnel_dielectric, ggx_reflection, sample_visible_normal, smith_g1,
+    smith_g2, Frame,
+};
+use super::ray::Ray;
+use super::texture::{color_or_texture, Texture, TextureSettings};
+use super::vec::{Color, Vec3};
+
+// The parameters of Disney's principled BSDF, as artists know them. Everything but `base_color` is
+// in [0, 1] (bar `ior`), and has the default Disney gives it.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PrincipledSettings {
+    #[serde(deserialize_with = "color_or_texture")]
+    base_color: TextureSettings,
+    // 0 by default: a dielectric, like plastic. 1 is a metal tinted by the base colour.
+    metallic: Option<f64>,
+    // 0.5 by default.
+    roughness: Option<f64>,
+    // The strength of the dielectric's reflections; the default 0.5 is exactly what `ior` gives.
+    specular: Option<f64>,
+    // A soft rim at grazing angles, for cloth. 0 by default.
+    sheen: Option<f64>,
+    // How much the sheen takes on the base colour; 0.5 by default.
+    sheen_tint: Option<f64>,
+    // A second, colourless and glossy specular layer on top, like varnish. 0 by default.
+    clearcoat: Option<f64>,
+    // 1 (sharp) by default.
+    clearcoat_gloss: Option<f64>,
+    // How much of the dielectric is glass rather than diffuse. 0 by default.
+    transmission: Option<f64>,
+    // 1.5 by default.
+    ior: Option<f64>,
+}
+
+// Schlick's approximation of the Fresnel reflectance, from the reflectance head on.
+fn schlick(f0: Color, cos: f64) -> Color {
+    let weight = (1.0 - cos.clamp(0.0, 1.0)).powi(5);
+    f0 + weight * (Color::new(1.0, 1.0, 1.0) - f0)
+}
+
+fn luminance(c: Color) -> f64 {
+    0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()
+}
+
+// Burley's principled BSDF ("Physically Based Shading at Disney", 2012), with a transmission lobe
+// as in the 2015 extension. Each scatter picks one lobe at random, in proportion to how much it
+// contributes.
+pub struct Principled {
+    base_color: Arc<dyn Texture>,
+    metallic: f64,
+    roughness: f64,
+    alpha: f64,
+    specular: f64,
+    sheen: f64,
+    sheen_tint: f64,
+    clearcoat: f64,
+    clearcoat_alpha: f64,
+    transmission: f64,
+    ior: f64,
+}
+
+impl Principled {
+    // Fails if the base colour is an image that can't be loaded.
+    pub fn new(settings: &PrincipledSettings) -> io::Result<Principled> {
+        let roughness = settings.roughness.unwrap_or(0.5).clamp(0.0, 1.0);
+        let clearcoat_gloss = settings.clearcoat_gloss.unwrap_or(1.0).clamp(0.0, 1.0);
+        Ok(Principled {
+            base_color: settings.base_color.to_texture()?,
+            metallic: settings.metallic.unwrap_or(0.0).clamp(0.0, 1.0),
+            roughness,
+            alpha: alpha_from_roughness(roughness),
+            specular: settings.specular.unwrap_or(0.5).max(0.0),
+            sheen: settings.sheen.unwrap_or(0.0).max(0.0),
+            sheen_tint: settings.sheen_tint.unwrap_or(0.5).clamp(0.0, 1.0),
+            clearcoat: settings.clearcoat.unwrap_or(0.0).clamp(0.0, 1.0),
+            // Disney's clearcoat runs from a roughness of 0.1 down to 0.001.
+            clearcoat_alpha: 0.1 + (0.001 - 0.1) * clearcoat_gloss,
+            transmission: settings.transmission.unwrap_or(0.0).clamp(0.0, 1.0),
+            ior: settings.ior.unwrap_or(1.5),
+        })
+    }
+
+    // The ratio of the indices of refraction, going into the surface from the side it was hit.
+    fn eta(&self, rec: &HitRecord) -> f64 {
+        if rec.front_face {
+            1.0 / self.ior
+        } else {
+            self.ior
+        }
+    }
+
+    // The dielectric's specular reflectance, for a cosine `cos` with the microfacet (or the
+    // macro surface). `specular` scales the reflections, except for total internal reflection.
+    fn reflectance(&self, cos: f64, eta: f64) -> f64 {
+        let fresnel = fresnel_dielectric(cos, eta);
+        if fresnel < 1.0 {
+            (2.0 * self.specular * fresnel).min(1.0)
+        } else {
+            1.0
+        }
+    }
+
+    // The clearcoat sits on top: it takes its Fresnel share of the light (0.04 head on, like a
+    // varnish) and lets the rest through to the layers below.
+    fn coat(&self, wo: Vec3) -> f64 {
+        0.25 * self.clearcoat * schlick(Color::new(0.04, 0.04, 0.04), wo.z()).x()
+    }
+
+    // Reflects wo (local) off a GGX microfacet, returning the direction and the masking weight.
+    fn sample_reflection(wo: Vec3, alpha: f64) -> Option<(Vec3, Vec3, f64)> {
+        let mut rng = rand::thread_rng();
+        let m = sample_visible_normal(wo, alpha, rng.gen(), rng.gen());
+        let wi = (-1.0 * wo).reflect(m);
+        if wi.z() <= 0.0 {
+            return None;
+        }
+        Some((wi, m, smith_g2(wo, wi, alpha) / smith_g1(wo, alpha)))
+    }
+
+    // The diffuse lobe, with Disney's retro-reflection at grazing angles, and the sheen, both
+    // times pi.
+    fn diffuse(&self, wo: Vec3, wi: Vec3, base_color: Color) -> Color {
+        let h = (wi + wo).normalized();
+        let cos_d = wi.dot(h);
+
+        let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+        let retro = |cos: f64| 1.0 + (fd90 - 1.0) * (1.0 - cos).powi(5);
+        let diffuse = retro(wi.z()) * retro(wo.z()) * base_color;
+
+        // The sheen lobe has no 1/pi, so it gains a pi here.
+        let tint = if luminance(base_color) > 0.0 {
+            base_color / luminance(base_color)
+        } else {
+            Color::new(1.0, 1.0, 1.0)
+        };
+        let sheen_color =
+            Color::new(1.0, 1.0, 1.0) + self.sheen_tint * (tint - Color::new(1.0, 1.0, 1.0));
+        let sheen = PI * self.sheen * (1.0 - cos_d).powi(5) * sheen_color;
+
+        diffuse + sheen
+    }
+
+    // The diffuse lobe and the sheen, sampled like a Lambertian, against whose density the pi
+    // cancels.
+    fn sample_diffuse(&self, wo: Vec3, base_color: Color) -> Option<(Color, Vec3)> {
+        let wi = Vec3::random_cosine_direction();
+        if wi.z() <= 0.0 {
+            return None;
+        }
+        Some((self.diffuse(wo, wi, base_color), wi))
+    }
+
+    // The BSDF times the cosine for the reflecting lobes, and the density with which `scatter`
+    // picks `direction` through any of them: each lobe's, times the chance of that lobe.
+    fn eval_reflection(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        direction: Vec3,
+    ) -> Option<(Color, f64)> {
+        if direction.dot(rec.normal) <= 0.0 {
+            return None;
+        }
+        let frame = Frame::new(rec.shading_normal);
+        let wo = frame.to_local(-1.0 * r_in.direction().normalized());
+        let wi = frame.to_local(direction.normalized());
+        let (h, f, pdf) = ggx_reflection(wo, wi, self.alpha)?;
+        let (_, coat_f, coat_pdf) = ggx_reflection(wo, wi, self.clearcoat_alpha)?;
+        let base_color = self.base_color.value(rec.u, rec.v, rec.p);
+        let white = Color::new(1.0, 1.0, 1.0);
+
+        let coat = self.coat(wo);
+        let metal = (1.0 - coat) * self.metallic;
+        let dielectric = (1.0 - coat) * (1.0 - self.metallic);
+        let eta = self.eta(rec);
+        let specular = self.reflectance(wo.z(), eta);
+        let diffuse = dielectric * (1.0 - self.transmission) * (1.0 - specular);
+
+        let value = (0.25 * self.clearcoat * schlick(Color::new(0.04, 0.04, 0.04), wo.dot(h)).x())
+            * coat_f
+            * white
+            + (metal * f) * schlick(base_color, wo.dot(h))
+            + (dielectric * self.reflectance(wo.dot(h), eta) * f) * white
+            + (diffuse * wi.z() / PI) * self.diffuse(wo, wi, base_color);
+        // Glass reflects by the microfacet's reflectance, plastic by the macro surface's.
+        let chance_specular = self.transmission * self.reflectance(wo.dot(h), eta)
+            + (1.0 - self.transmission) * specular;
+        let pdf = coat * coat_pdf
+            + metal * pdf
+            + dielectric * chance_specular * pdf
+            + diffuse * wi.z() / PI;
+        Some((value, pdf))
+    }
+}
+
+impl Scatter for Principled {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> Option<ScatterRecord> {
+        let frame = Frame::new(rec.shading_normal);
+        let wo = frame.to_local(-1.0 * r_in.direction().normalized());
+        if wo.z() <= 0.0 {
+            return None;
+        }
+        let base_color = self.base_color.value(rec.u, rec.v, rec.p);
+        let white = Color::new(1.0, 1.0, 1.0);
+        let mut rng = rand::thread_rng();
+        let eta = self.eta(rec);
+
+        let coat = self.coat(wo);
+        let (attenuation, wi) = if rng.gen::<f64>() < coat {
+            let (wi, m, masking) = Self::sample_reflection(wo, self.clearcoat_alpha)?;
+            // The layer was picked by the Fresnel term for the macro surface; correct that to the
+            // one for the microfacet actually hit.
+            let fresnel = schlick(Color::new(0.04, 0.04, 0.04), wo.dot(m)).x()
+                / schlick(Color::new(0.04, 0.04, 0.04), wo.z()).x();
+            (masking * fresnel * white, wi)
+        } else if rng.gen::<f64>() < self.metallic {
+            // A metal reflects everything, tinted by the base colour head on.
+            let (wi, m, masking) = Self::sample_reflection(wo, self.alpha)?;
+            (masking * schlick(base_color, wo.dot(m)), wi)
+        } else if rng.gen::<f64>() < self.transmission {
+            // Glass: a specular reflection on a microfacet, and whatever isn't reflected is
+            // transmitted.
+            let m = sample_visible_normal(wo, self.alpha, rng.gen(), rng.gen());
+            let cos_o = wo.dot(m);
+            let wi = if rng.gen::<f64>() < self.reflectance(cos_o, eta) {
+                let wi = (-1.0 * wo).reflect(m);
+                if wi.z() <= 0.0 {
+                    return None;
+                }
+                wi
+            } else {
+                let cos_t = (1.0 - eta * eta * (1.0 - cos_o * cos_o)).sqrt();
+                let wi = (eta * cos_o - cos_t) * m - eta * wo;
+                if wi.z() >= 0.0 {
+                    return None;
+                }
+                wi
+            };
+            let masking = smith_g2(wo, wi, self.alpha) / smith_g1(wo, self.alpha);
+            let tint = if wi.z() > 0.0 { white } else { base_color };
+            (masking * tint, wi)
+        } else {
+            // Plastic: a specular reflection, picked by the reflectance of the macro surface so
+            // that what's left for the diffuse lobe doesn't depend on the microfacet.
+            let specular = self.reflectance(wo.z(), eta);
+            if rng.gen::<f64>() < specular {
+                let (wi, m, masking) = Self::sample_reflection(wo, self.alpha)?;
+                let fresnel = self.reflectance(wo.dot(m), eta) / specular;
+                (masking * fresnel * white, wi)
+            } else {
+                self.sample_diffuse(wo, base_color)?
+            }
+        };
+
+        // Transmission is the one lobe without a density.
+        let direction = frame.to_world(wi);
+        Some(ScatterRecord {
+            attenuation,
+            scattered: Ray::new(rec.p, direction),
+            pdf: (wi.z() > 0.0).then(|| self.pdf(r_in, rec, direction)),
+        })
+    }
+
+    fn eval(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Color {
+        self.eval_reflection(r_in, rec, direction)
+            .map_or(Color::new(0.0, 0.0, 0.0), |(value, _)| value)
+    }
+
+    fn pdf(&self, r_in: &Ray, rec: &HitRecord, direction: Vec3) -> f64 {
+        self.eval_reflection(r_in, rec, direction)
+            .map_or(0.0, |(_, pdf)| pdf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::tests::assert_density_agrees_with_sampling;
+    use crate::material::Lambertian;
+    use crate::vec::Point3;
+
+    fn settings(json: &str) -> PrincipledSettings {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn principled_scatters_stay_on_the_right_side() {
+        let rec = HitRecord {
+            p: Point3::new(0.0, 0.0, 0.0),
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            shading_normal: Vec3::new(0.0, 1.0, 0.0),
+            mat: Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+            t: 1.0,
+            u: 0.0,
+            v: 0.0,
+            dpdu: Vec3::new(1.0, 0.0, 0.0),
+            dpdv: Vec3::new(0.0, 0.0, 1.0),
+            front_face: true,
+        };
+        let r_in = Ray::new(Point3::new(-1.0, 1.0, 0.0), Vec3::new(1.0, -1.0, 0.0));
+
+        // Opaque materials only reflect; glass also transmits, into the surface.
+        let plastic = Principled::new(&settings(
+            r#"{"base_color": {"e": [0.8, 0.2, 0.2]}, "clearcoat": 1, "sheen": 1}"#,
+        ))
+        .unwrap();
+        let glass = Principled::new(&settings(
+            r#"{"base_color": {"e": [1, 1, 1]}, "transmission": 1, "roughness": 0.1}"#,
+        ))
+        .unwrap();
+        let mut transmitted = 0;
+        for _ in 0..1000 {
+            if let Some(srec) = plastic.scatter(&r_in, &rec) {
+                assert!(srec.scattered.direction().y() > 0.0);
+                assert!((0..3).all(|i| srec.attenuation[i] >= 0.0));
+            }
+            if let Some(srec) = glass.scatter(&r_in, &rec) {
+                if srec.scattered.direction().y() < 0.0 {
+                    transmitted += 1;
+                }
+            }
+        }
+        assert!(transmitted > 800);
+    }
+
+    #[test]
+    fn principled_samples_agree_with_its_density() {
+        // Every lobe at once: clearcoat, metal, glass, specular, diffuse and sheen.
+        let principled = Principled::new(&settings(
+            r#"{"base_color": {"e": [0.8, 0.5, 0.2]}, "metallic": 0.3, "clearcoat": 1,
+                "clearcoat_gloss": 0, "sheen": 1, "transmission": 0.3}"#,
+        ))
+        .unwrap();
+        assert_density_agrees_with_sampling(&principled);
+    }
+}
@@ -3,11 +3,23 @@ use super::vec::{Point3, Vec3};
 pub struct Ray {
     orig: Point3,
     dir: Vec3,
+    // In nanometres, for rays that carry a single wavelength in spectral mode.
+    wavelength: Option<f64>,
 }
 
 impl Ray {
     pub fn new(orig: Point3, dir: Vec3) -> Ray {
-        Ray { orig, dir }
+        Ray {
+            orig,
+            dir,
+            wavelength: None,
+        }
+    }
+    pub fn with_wavelength(self, wavelength: Option<f64>) -> Ray {
+        Ray { wavelength, ..self }
+    }
+    pub fn wavelength(&self) -> Option<f64> {
+        self.wavelength
     }
     pub fn origin(&self) -> Point3 {
         self.orig
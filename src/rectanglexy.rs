@@ -1,6 +1,7 @@
 use std::sync::Arc;
 
 use crate::{
+    aabb::Aabb,
     hit::{Hit, HitRecord},
     material::Scatter,
     ray::Ray,
@@ -24,10 +25,13 @@ impl RectangleXY {
 }
 
 impl Hit for RectangleXY {
-    fn hit(&self, r: &Ray, _t_min: f64, _t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
         let z = self.min_point.z();
 
         let t = (z - r.origin().z()) / r.direction().z();
+        if !(t_min..=t_max).contains(&t) {
+            return None;
+        }
         let p = r.origin() + r.direction() * t;
 
         if p.x() <= self.max_point.x()
@@ -35,17 +39,28 @@ impl Hit for RectangleXY {
             && p.y() <= self.max_point.y()
             && p.y() >= self.min_point.y()
         {
-            let rec = HitRecord {
+            let mut rec = HitRecord {
                 t,
                 p,
                 mat: self.mat.clone(),
                 normal: Vec3::new(0.0, 0.0, 0.0),
+                shading_normal: Vec3::new(0.0, 0.0, 0.0),
+                u: (p.x() - self.min_point.x()) / (self.max_point.x() - self.min_point.x()),
+                v: (p.y() - self.min_point.y()) / (self.max_point.y() - self.min_point.y()),
+                dpdu: Vec3::new(self.max_point.x() - self.min_point.x(), 0.0, 0.0),
+                dpdv: Vec3::new(0.0, self.max_point.y() - self.min_point.y(), 0.0),
                 front_face: false,
             };
-            // todo: always front face?
+            // The front face looks towards +z.
+            rec.set_face_normal(r, Vec3::new(0.0, 0.0, 1.0));
             Some(rec)
         } else {
-            return None;
+            None
         }
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        // The rectangle has no thickness in z, so pad the box to keep it from being degenerate.
+        Some(Aabb::new(self.min_point, self.max_point).padded(0.0001))
+    }
 }
@@ -0,0 +1,1114 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use rand::Rng;
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::{
+    aabb::Aabb,
+    bump::{Bump, NormalMap},
+    bvh::Bvh,
+    grid::load_grid,
+    hit::{Hit, World},
+    image::load_image,
+    light::{DirectionalLight, Lights, PointLight, RectangleLight, SphereLight, SpotLight},
+    material::{Dielectric, DiffuseLight, Ior, Lambertian, Metal, Scatter},
+    medium::{ConstantMedium, GridMedium, HenyeyGreenstein},
+    mesh::{MeshData, TriangleMesh},
+    microfacet::{Conductor, RoughConductor, RoughDielectric},
+    obj::load_obj,
+    plane::Plane,
+    principled::{Principled, PrincipledSettings},
+    rectanglexy::RectangleXY,
+    sphere::Sphere,
+    texture::{color_or_texture, ImageTexture, TextureSettings, WrapMode},
+    triangle::Triangle,
+    vec::{Color, Point3, Vec3},
+};
+
+// Exactly one material, named by its `type`, e.g. {"type": "metal", "albedo": ..., "fuzz": 0.1}.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum MaterialSettings {
+    Lambertian {
+        #[serde(deserialize_with = "color_or_texture")]
+        albedo: TextureSettings,
+    },
+    Metal {
+        #[serde(deserialize_with = "color_or_texture")]
+        albedo: TextureSettings,
+        fuzz: f64,
+    },
+    Dielectric {
+        ir: IorSettings,
+        // The colour white light takes on after travelling `distance` (1 by default) through the
+        // inside. Clear without one.
+        color: Option<Color>,
+        distance: Option<f64>,
+    },
+    DiffuseLight {
+        emit: Color,
+    },
+    // Phase functions, for the particles in volumes.
+    Isotropic {
+        #[serde(deserialize_with = "color_or_texture")]
+        albedo: TextureSettings,
+    },
+    HenyeyGreenstein {
+        #[serde(deserialize_with = "color_or_texture")]
+        albedo: TextureSettings,
+        // From -1 (scattering straight back) to 1 (straight on).
+        g: f64,
+    },
+    // A rough metal: one of the measured `conductor`s, or one with its own complex index of
+    // refraction `eta` + i `k`.
+    RoughConductor {
+        conductor: Option<Conductor>,
+        eta: Option<Color>,
+        k: Option<Color>,
+        roughness: f64,
+    },
+    RoughDielectric {
+        ir: f64,
+        roughness: f64,
+    },
+    Principled(PrincipledSettings),
+    // `material`, shaded with normals from a tangent-space normal map image.
+    NormalMap {
+        material: Box<MaterialSettings>,
+        path: String,
+        // Repeat by default.
+        wrap: Option<WrapMode>,
+        // 1 by default; smaller values flatten the map out.
+        strength: Option<f64>,
+    },
+    // `material`, shaded as if raised by `scale` times the `height` texture. Height images are
+    // read as linear values, not sRGB colours.
+    Bump {
+        material: Box<MaterialSettings>,
+        height: TextureSettings,
+        scale: f64,
+    },
+}
+
+impl MaterialSettings {
+    // Fails if a texture image can't be loaded, or the settings don't make sense.
+    fn to_scatter(&self) -> Result<Arc<dyn Scatter>, Box<dyn Error>> {
+        Ok(match self {
+            MaterialSettings::Lambertian { albedo } => {
+                Arc::new(Lambertian::from_texture(albedo.to_texture()?))
+            }
+            MaterialSettings::Metal { albedo, fuzz } => {
+                Arc::new(Metal::from_texture(albedo.to_texture()?, *fuzz))
+            }
+            MaterialSettings::Dielectric {
+                ir,
+                color,
+                distance,
+            } => {
+                let distance = distance.unwrap_or(1.0);
+                if !(distance > 0.0 && distance.is_finite()) {
+                    return Err(Box::new(SceneError(format!(
+                        "a dielectric's `distance` needs to be a positive number, not {}",
+                        distance
+                    ))));
+                }
+                match color {
+                    Some(color) => Arc::new(Dielectric::tinted(ir.to_ior(), *color, distance)),
+                    None => Arc::new(Dielectric::new(ir.to_ior())),
+                }
+            }
+            MaterialSettings::DiffuseLight { emit } => Arc::new(DiffuseLight::new(*emit)),
+            MaterialSettings::Isotropic { albedo } => {
+                Arc::new(HenyeyGreenstein::isotropic(albedo.to_texture()?))
+            }
+            MaterialSettings::HenyeyGreenstein { albedo, g } => {
+                Arc::new(HenyeyGreenstein::new(albedo.to_texture()?, *g))
+            }
+            MaterialSettings::RoughConductor {
+                conductor,
+                eta,
+                k,
+                roughness,
+            } => {
+                let (eta, k) = match (conductor, eta, k) {
+                    (Some(conductor), None, None) => conductor.ior(),
+                    (None, Some(eta), Some(k)) => (*eta, *k),
+                    _ => {
+                        return Err(Box::new(SceneError(
+                            "a rough conductor needs either a `conductor`, or `eta` and `k`"
+                                .to_string(),
+                        )))
+                    }
+                };
+                Arc::new(RoughConductor::new(eta, k, *roughness))
+            }
+            MaterialSettings::RoughDielectric { ir, roughness } => {
+                Arc::new(RoughDielectric::new(*ir, *roughness))
+            }
+            MaterialSettings::Principled(settings) => Arc::new(Principled::new(settings)?),
+            MaterialSettings::NormalMap {
+                material,
+                path,
+                wrap,
+                strength,
+            } => {
+                let map = ImageTexture::new(
+                    load_image(Path::new(path), false)?,
+                    wrap.unwrap_or(WrapMode::Repeat),
+                );
+                Arc::new(NormalMap::new(
+                    material.to_scatter()?,
+                    Arc::new(map),
+                    strength.unwrap_or(1.0),
+                ))
+            }
+            MaterialSettings::Bump {
+                material,
+                height,
+                scale,
+            } => Arc::new(Bump::new(
+                material.to_scatter()?,
+                height.to_data_texture()?,
+                *scale,
+            )),
+        })
+    }
+}
+
+// An index of refraction: a plain number, one of the glasses below, or a formula that makes it
+// vary with wavelength, e.g. {"type": "cauchy", "a": 1.5046, "b": 0.0042}. Only spectral mode
+// shows the difference.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum IorSettings {
+    Constant(f64),
+    Glass(Glass),
+    Formula(IorFormula),
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Glass {
+    Bk7,
+    FusedSilica,
+    Diamond,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum IorFormula {
+    Cauchy { a: f64, b: f64 },
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl IorSettings {
+    fn to_ior(&self) -> Ior {
+        match self {
+            IorSettings::Constant(n) => Ior::Constant(*n),
+            // Sellmeier coefficients for Schott N-BK7, Malitson's fused silica, and diamond.
+            IorSettings::Glass(Glass::Bk7) => Ior::Sellmeier {
+                b: [1.03961212, 0.231792344, 1.01046945],
+                c: [0.00600069867, 0.0200179144, 103.560653],
+            },
+            IorSettings::Glass(Glass::FusedSilica) => Ior::Sellmeier {
+                b: [0.6961663, 0.4079426, 0.8974794],
+                c: [
+                    0.0684043f64.powi(2),
+                    0.1162414f64.powi(2),
+                    9.896161f64.powi(2),
+                ],
+            },
+            IorSettings::Glass(Glass::Diamond) => Ior::Sellmeier {
+                b: [0.3306, 4.3356, 0.0],
+                c: [0.175f64.powi(2), 0.106f64.powi(2), 0.0],
+            },
+            IorSettings::Formula(IorFormula::Cauchy { a, b }) => Ior::Cauchy { a: *a, b: *b },
+            IorSettings::Formula(IorFormula::Sellmeier { b, c }) => Ior::Sellmeier { b: *b, c: *c },
+        }
+    }
+}
+
+// The `material` of an object: either the name of a material in the scene's `materials`, or a
+// material of its own. Besides the tagged form this still accepts the old shape, with one key per
+// kind of material (e.g. {"metal": {"albedo": ..., "fuzz": 0.1}}), as long as only one kind is set.
+pub enum ObjectMaterial {
+    Named(String),
+    Inline(MaterialSettings),
+}
+
+impl ObjectMaterial {
+    fn resolve(
+        &self,
+        library: &HashMap<String, Arc<dyn Scatter>>,
+    ) -> Result<Arc<dyn Scatter>, Box<dyn Error>> {
+        match self {
+            ObjectMaterial::Named(name) => library.get(name).cloned().ok_or_else(|| {
+                let mut known: Vec<&String> = library.keys().collect();
+                known.sort();
+                SceneError(format!(
+                    "unknown material `{}` (the scene's `materials` are: {:?})",
+                    name, known
+                ))
+                .into()
+            }),
+            ObjectMaterial::Inline(settings) => settings.to_scatter(),
+        }
+    }
+}
+
+static WARNED_ABOUT_LEGACY_MATERIALS: AtomicBool = AtomicBool::new(false);
+
+fn from_legacy_material(fields: Map<String, Value>) -> Result<MaterialSettings, String> {
+    let kinds: Vec<(String, Value)> = fields
+        .into_iter()
+        .filter(|(_, value)| !value.is_null())
+        .collect();
+
+    match kinds.len() {
+        0 => Err("a material needs a `type`, e.g. {\"type\": \"lambertian\", ...}".to_string()),
+        1 => {
+            if !WARNED_ABOUT_LEGACY_MATERIALS.swap(true, Ordering::Relaxed) {
+                eprintln!(
+                    "warning: materials like {{\"{0}\": {{...}}}} are deprecated, \
+                     write {{\"type\": \"{0}\", ...}} instead",
+                    kinds[0].0
+                );
+            }
+            let (kind, settings) = kinds.into_iter().next().unwrap();
+            let mut settings = match settings {
+                Value::Object(settings) => settings,
+                _ => return Err(format!("the settings for `{}` need to be an object", kind)),
+            };
+            settings.insert("type".to_string(), Value::String(kind));
+            MaterialSettings::deserialize(Value::Object(settings)).map_err(|e| e.to_string())
+        }
+        _ => {
+            let names: Vec<String> = kinds
+                .iter()
+                .map(|(kind, _)| format!("`{}`", kind))
+                .collect();
+            Err(format!(
+                "ambiguous material: it sets {} at once, but an object can only have one material",
+                names.join(" and ")
+            ))
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ObjectMaterial {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ObjectMaterial, D::Error> {
+        let fields = match Value::deserialize(deserializer)? {
+            Value::String(name) => return Ok(ObjectMaterial::Named(name)),
+            Value::Object(fields) => fields,
+            _ => {
+                return Err(de::Error::custom(
+                    "a material needs to be a material name or an object",
+                ))
+            }
+        };
+        let settings = if fields.contains_key("type") {
+            MaterialSettings::deserialize(Value::Object(fields)).map_err(de::Error::custom)?
+        } else {
+            from_legacy_material(fields).map_err(de::Error::custom)?
+        };
+        Ok(ObjectMaterial::Inline(settings))
+    }
+}
+
+#[derive(Deserialize)]
+pub struct SceneSettings {
+    // Materials that objects can share by name. Each becomes a single Scatter, however many
+    // objects use it.
+    materials: Option<HashMap<String, MaterialSettings>>,
+    spheres: Option<Vec<SphereSettings>>,
+    planes: Option<Vec<PlaneSettings>>,
+    rectangles: Option<Vec<RectangleSettings>>,
+    triangles: Option<Vec<TriangleSettings>>,
+    meshes: Option<Vec<MeshSettings>>,
+    volumes: Option<Vec<VolumeSettings>>,
+    grids: Option<Vec<GridSettings>>,
+    lights: Option<Vec<LightSettings>>,
+}
+
+#[derive(Deserialize)]
+struct SphereSettings {
+    center: Point3,
+    radius: f64,
+    material: ObjectMaterial,
+}
+
+#[derive(Deserialize)]
+struct PlaneSettings {
+    point1: Point3,
+    point2: Point3,
+    normal: Vec3,
+    material: ObjectMaterial,
+}
+
+// A rectangle in the plane z = min_point.z, facing +z.
+#[derive(Deserialize)]
+struct RectangleSettings {
+    min_point: Point3,
+    max_point: Point3,
+    material: ObjectMaterial,
+}
+
+#[derive(Deserialize)]
+struct TriangleSettings {
+    // Counter-clockwise when seen from the front.
+    vertices: [Point3; 3],
+    material: ObjectMaterial,
+}
+
+// Either an inline mesh or a Wavefront OBJ file.
+//
+// An inline mesh needs `vertices`, `indices` and a `material`. Each entry of `indices` is one face,
+// given as three indices into `vertices` (and `normals` and `uvs`, which need one entry per vertex
+// when present).
+//
+// An OBJ file's groups use the materials from its MTL libraries, unless `material` is given, in
+// which case that is used for the whole mesh instead.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MeshSettings {
+    obj: Option<String>,
+    vertices: Option<Vec<Point3>>,
+    indices: Option<Vec<[usize; 3]>>,
+    normals: Option<Vec<Vec3>>,
+    uvs: Option<Vec<[f64; 2]>>,
+    material: Option<ObjectMaterial>,
+}
+
+// Smoke or fog of constant `density` filling a closed shape. Its material should be one of the
+// phase functions, `isotropic` or `henyey_greenstein`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct VolumeSettings {
+    boundary: BoundarySettings,
+    density: f64,
+    material: ObjectMaterial,
+}
+
+// Smoke or clouds whose density comes from a voxel grid file (see `load_grid`), like a
+// simulation exported from another tool.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GridSettings {
+    path: String,
+    // Needed for .raw files, which don't say.
+    resolution: Option<[usize; 3]>,
+    // The corners of the box the grid fills. By default, the bounds in a .vol file.
+    min: Option<Point3>,
+    max: Option<Point3>,
+    // Multiplies the densities in the file; 1 by default.
+    density: Option<f64>,
+    material: ObjectMaterial,
+}
+
+impl GridSettings {
+    fn to_medium(
+        &self,
+        library: &HashMap<String, Arc<dyn Scatter>>,
+    ) -> Result<GridMedium, Box<dyn Error>> {
+        let density = self.density.unwrap_or(1.0);
+        if !(density >= 0.0 && density.is_finite()) {
+            return Err(Box::new(SceneError(format!(
+                "{}: the grid's `density` needs to be a number that isn't negative, not {}",
+                self.path, density
+            ))));
+        }
+        let grid = load_grid(Path::new(&self.path), self.resolution)?;
+        let bounds = match (self.min, self.max, grid.bounds) {
+            (Some(min), Some(max), _) => Aabb::from_points(min, max),
+            (None, None, Some(bounds)) => bounds,
+            _ => {
+                return Err(Box::new(SceneError(format!(
+                    "{}: the grid needs both `min` and `max`, or neither and a .vol file",
+                    self.path
+                ))))
+            }
+        };
+        Ok(GridMedium::new(
+            grid,
+            bounds,
+            density,
+            self.material.resolve(library)?,
+        ))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum BoundarySettings {
+    Sphere {
+        center: Point3,
+        radius: f64,
+    },
+    // A closed mesh, from an OBJ file or inline like a mesh's `vertices` and `indices`.
+    Mesh {
+        obj: Option<String>,
+        vertices: Option<Vec<Point3>>,
+        indices: Option<Vec<[usize; 3]>>,
+    },
+}
+
+impl BoundarySettings {
+    // The boundary as an object, with `mat` (which nothing sees) as its surface.
+    fn to_hit(&self, mat: Arc<dyn Scatter>) -> Result<Box<dyn Hit>, Box<dyn Error>> {
+        match self {
+            BoundarySettings::Sphere { center, radius } => {
+                Ok(Box::new(Sphere::new(*center, *radius, mat)))
+            }
+            BoundarySettings::Mesh {
+                obj: Some(obj),
+                vertices: None,
+                indices: None,
+            } => {
+                let mut meshes = World::new();
+                for group in load_obj(Path::new(obj))?.groups {
+                    meshes.push(Box::new(TriangleMesh::new(
+                        Arc::new(group.mesh),
+                        mat.clone(),
+                    )));
+                }
+                Ok(Box::new(Bvh::new(meshes)))
+            }
+            BoundarySettings::Mesh {
+                obj: None,
+                vertices: Some(vertices),
+                indices: Some(indices),
+            } => {
+                let mesh = inline_mesh(vertices, indices, &None, &None)?;
+                Ok(Box::new(TriangleMesh::new(Arc::new(mesh), mat)))
+            }
+            BoundarySettings::Mesh { .. } => Err(Box::new(SceneError(
+                "a mesh boundary needs either an `obj` file, or `vertices` and `indices`"
+                    .to_string(),
+            ))),
+        }
+    }
+}
+
+// Lights that are sampled directly. Glowing shapes are found far more reliably this way than by
+// scattered rays; small ones especially need to be lights rather than objects with a
+// `diffuse_light` material. Point, spot and directional lights have no shape at all.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum LightSettings {
+    Sphere {
+        center: Point3,
+        radius: f64,
+        emit: Color,
+    },
+    // Like `rectangles`, in the xy plane; it gives off light on both sides.
+    Rectangle {
+        min_point: Point3,
+        max_point: Point3,
+        emit: Color,
+    },
+    Point {
+        position: Point3,
+        intensity: Color,
+    },
+    // Shines along `direction`, out to `angle` degrees from it. It fades out from `inner_angle`,
+    // which by default is the same, for a hard edge.
+    Spot {
+        position: Point3,
+        direction: Vec3,
+        intensity: Color,
+        angle: f64,
+        inner_angle: Option<f64>,
+    },
+    // Like the sun: light travelling along `direction` from infinitely far away.
+    Directional {
+        direction: Vec3,
+        irradiance: Color,
+    },
+}
+
+impl LightSettings {
+    // Adds the light, and the shape rays can hit if it has one. Fails for shapes that have no
+    // area to sample.
+    fn add_to(&self, world: &mut World, lights: &mut Lights) -> Result<(), Box<dyn Error>> {
+        match self {
+            LightSettings::Sphere {
+                center,
+                radius,
+                emit,
+            } => {
+                // A negative radius would turn the sphere inside out, leaving it dark outside.
+                if !(*radius > 0.0 && radius.is_finite()) {
+                    return Err(Box::new(SceneError(format!(
+                        "a sphere light's `radius` needs to be a positive number, not {}",
+                        radius
+                    ))));
+                }
+                lights
+                    .area
+                    .push(Box::new(SphereLight::new(*center, *radius, *emit)));
+                world.push(Box::new(Sphere::new(
+                    *center,
+                    *radius,
+                    Arc::new(DiffuseLight::new(*emit)),
+                )));
+            }
+            LightSettings::Rectangle {
+                min_point,
+                max_point,
+                emit,
+            } => {
+                if (0..3).any(|i| !(min_point[i].is_finite() && max_point[i].is_finite())) {
+                    return Err(Box::new(SceneError(
+                        "a rectangle light's corners need to be finite".to_string(),
+                    )));
+                }
+                // The rectangle lies in a plane of constant z, with some width and height in it.
+                if min_point.z() != max_point.z() {
+                    return Err(Box::new(SceneError(format!(
+                        "a rectangle light's corners need the same z, not {} and {}",
+                        min_point.z(),
+                        max_point.z()
+                    ))));
+                }
+                if min_point.x() == max_point.x() || min_point.y() == max_point.y() {
+                    return Err(Box::new(SceneError(
+                        "a rectangle light's corners need different x and y, or it has no area"
+                            .to_string(),
+                    )));
+                }
+                // The corners may come in either order.
+                let min = Point3::new(
+                    min_point.x().min(max_point.x()),
+                    min_point.y().min(max_point.y()),
+                    min_point.z(),
+                );
+                let max = Point3::new(
+                    min_point.x().max(max_point.x()),
+                    min_point.y().max(max_point.y()),
+                    max_point.z(),
+                );
+                lights
+                    .area
+                    .push(Box::new(RectangleLight::new(min, max, *emit)));
+                world.push(Box::new(RectangleXY::new(
+                    min,
+                    max,
+                    Arc::new(DiffuseLight::new(*emit)),
+                )));
+            }
+            LightSettings::Point {
+                position,
+                intensity,
+            } => lights
+                .punctual
+                .push(Box::new(PointLight::new(*position, *intensity))),
+            LightSettings::Spot {
+                position,
+                direction,
+                intensity,
+                angle,
+                inner_angle,
+            } => lights.punctual.push(Box::new(SpotLight::new(
+                *position,
+                *direction,
+                *intensity,
+                *angle,
+                inner_angle.unwrap_or(*angle),
+            ))),
+            LightSettings::Directional {
+                direction,
+                irradiance,
+            } => lights
+                .punctual
+                .push(Box::new(DirectionalLight::new(*direction, *irradiance))),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct SceneError(pub String);
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for SceneError {}
+
+fn random_scene() -> World {
+    let mut rng = rand::thread_rng();
+    let mut world = World::new();
+
+    // let ground_mat = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+    let ground_mat = Arc::new(Metal::new(Color::new(0.9, 0.6, 0.5), 0.1));
+    let ground_sphere = Sphere::new(Point3::new(0.0, -1000.0, 0.0), 1000.0, ground_mat);
+
+    world.push(Box::new(ground_sphere));
+
+    for a in -3..=7 {
+        for b in -3..=7 {
+            let choose_mat: f64 = rng.gen();
+            let center = Point3::new(
+                (a as f64) + rng.gen_range(0.0..0.9),
+                0.2,
+                (b as f64) + rng.gen_range(0.0..0.9),
+            );
+
+            if choose_mat < 0.8 {
+                // Diffuse
+                let albedo = Color::random(0.0..1.0) * Color::random(0.0..1.0);
+                let sphere_mat = Arc::new(Lambertian::new(albedo));
+                let sphere = Sphere::new(center, 0.2, sphere_mat);
+
+                world.push(Box::new(sphere));
+            } else if choose_mat < 0.95 {
+                // Metal
+                let albedo = Color::random(0.4..1.0);
+                let fuzz = rng.gen_range(0.0..0.5);
+                let sphere_mat = Arc::new(Metal::new(albedo, fuzz));
+                let sphere = Sphere::new(center, 0.2, sphere_mat);
+
+                world.push(Box::new(sphere));
+            } else {
+                // Glass
+                let sphere_mat = Arc::new(Dielectric::new(Ior::Constant(1.5)));
+                let sphere = Sphere::new(center, 0.2, sphere_mat);
+
+                world.push(Box::new(sphere));
+            }
+        }
+    }
+
+    // let cube_mat = Arc::new(Metal::new(Color::new(0.7, 0.6, 0.5), 0.0));
+
+    // A cube is made of 6 sides. For simplicity, I will make these be perpendicular to the axes.
+    // {x, y, z}{min, max} are the bounding box for the rectangular prism that we are going to draw.
+    // todo probably should extract this function.
+    let xmin = 3.0;
+    let xmax = 4.0;
+    let ymin = 0.0;
+    let ymax = 1.0;
+    let zmin = -0.5;
+    let zmax = 0.5;
+
+    // let cube_material = Arc::new(Lambertian::new(Color::new(0.2, 0.8, 0.2)));
+    let cube_material = Arc::new(Metal::new(Color::new(0.3, 0.2, 0.1), 0.0));
+
+    world.push(Box::new(Plane::new(
+        Vec3::new(0.0, 0.0, 1.0),
+        Vec3::new(xmin, ymin, zmin),
+        Vec3::new(xmax, ymax, zmin),
+        cube_material.clone(),
+    )));
+    world.push(Box::new(Plane::new(
+        Vec3::new(0.0, 0.0, 1.0),
+        Vec3::new(xmin, ymin, zmax),
+        Vec3::new(xmax, ymax, zmax),
+        cube_material.clone(),
+    )));
+    world.push(Box::new(Plane::new(
+        Vec3::new(0.0, 1.0, 0.0),
+        Vec3::new(xmin, ymin, zmin),
+        Vec3::new(xmax, ymin, zmax),
+        cube_material.clone(),
+    )));
+    world.push(Box::new(Plane::new(
+        Vec3::new(0.0, 1.0, 0.0),
+        Vec3::new(xmin, ymax, zmin),
+        Vec3::new(xmax, ymax, zmax),
+        cube_material.clone(),
+    )));
+    world.push(Box::new(Plane::new(
+        Vec3::new(1.0, 0.0, 0.0),
+        Vec3::new(xmin, ymin, zmin),
+        Vec3::new(xmin, ymax, zmax),
+        cube_material.clone(),
+    )));
+    world.push(Box::new(Plane::new(
+        Vec3::new(1.0, 0.0, 0.0),
+        Vec3::new(xmax, ymin, zmin),
+        Vec3::new(xmax, ymax, zmax),
+        cube_material.clone(),
+    )));
+
+    // let mat1 = Arc::new(Dielectric::new(1.5));
+    // let mat2 = Arc::new(Lambertian::new(Color::new(0.4, 0.2, 0.1)));
+    // let mat3 = Arc::new(Metal::new(Color::new(0.7, 0.6, 0.5), 0.0));
+
+    // let sphere1 = Sphere::new(Point3::new(0.0, 1.0, 0.0), 1.0, mat1);
+    // let sphere2 = Sphere::new(Point3::new(-4.0, 1.0, 0.0), 1.0, mat2);
+    // let sphere3 = Sphere::new(Point3::new(4.0, 1.0, 0.0), 1.0, mat3);
+
+    // world.push(Box::new(sphere1));
+    // world.push(Box::new(sphere2));
+    // world.push(Box::new(sphere3));
+
+    // let mat1 = Arc::new(Dielectric::new(1.5));
+    // let mat2 = Arc::new(Lambertian::new(Color::new(0.4, 0.2, 0.1)));
+    // let mat2_copy = Arc::new(Lambertian::new(Color::new(0.4, 0.2, 0.1)));
+    // let mat3 = Arc::new(Metal::new(Color::new(0.7, 0.6, 0.5), 0.0));
+
+    // let sphere1 = Sphere::new(Point3::new(0.0, 1.0, 0.0), 1.0, mat1);
+    // let sphere2 = Sphere::new(Point3::new(-4.0, 1.0, 0.0), 1.0, mat2);
+    // let sphere2_int = Sphere::new(Point3::new(-4.0, 1.0, 0.0), -0.99, mat2_copy);
+    // let sphere3 = Sphere::new(Point3::new(4.0, 1.0, 0.0), 1.0, mat3);
+
+    // world.push(Box::new(sphere1));
+    // world.push(Box::new(sphere2));
+    // world.push(Box::new(sphere2_int));
+    // world.push(Box::new(sphere3));
+
+    world
+}
+
+// Builds the objects described by the preset (or a random scene if there are none) into a Bvh.
+pub fn construct_scene_from_settings(
+    scene_settings: &Option<SceneSettings>,
+) -> Result<(Bvh, Lights), Box<dyn Error>> {
+    if let Some(scene_settings) = scene_settings {
+        let mut world = World::new();
+        let mut lights = Lights::new();
+
+        let mut library = HashMap::new();
+        for (name, settings) in scene_settings.materials.iter().flatten() {
+            library.insert(name.clone(), settings.to_scatter()?);
+        }
+
+        if let Some(sphere_settings) = &scene_settings.spheres {
+            for sphere_setting in sphere_settings {
+                world.push(Box::new(Sphere::new(
+                    sphere_setting.center,
+                    sphere_setting.radius,
+                    sphere_setting.material.resolve(&library)?,
+                )));
+            }
+        }
+        if let Some(plane_settings) = &scene_settings.planes {
+            for plane_setting in plane_settings {
+                world.push(Box::new(Plane::new(
+                    plane_setting.normal,
+                    plane_setting.point1,
+                    plane_setting.point2,
+                    plane_setting.material.resolve(&library)?,
+                )));
+            }
+        }
+        if let Some(rectangle_settings) = &scene_settings.rectangles {
+            for rectangle_setting in rectangle_settings {
+                world.push(Box::new(RectangleXY::new(
+                    rectangle_setting.min_point,
+                    rectangle_setting.max_point,
+                    rectangle_setting.material.resolve(&library)?,
+                )));
+            }
+        }
+        if let Some(triangle_settings) = &scene_settings.triangles {
+            for triangle_setting in triangle_settings {
+                let [p0, p1, p2] = triangle_setting.vertices;
+                world.push(Box::new(Triangle::new(
+                    p0,
+                    p1,
+                    p2,
+                    triangle_setting.material.resolve(&library)?,
+                )));
+            }
+        }
+        if let Some(mesh_settings) = &scene_settings.meshes {
+            for mesh_setting in mesh_settings {
+                add_mesh(&mut world, mesh_setting, &library)?;
+            }
+        }
+        if let Some(volume_settings) = &scene_settings.volumes {
+            for volume_setting in volume_settings {
+                // Negative densities would put the scattering behind where rays start.
+                let density = volume_setting.density;
+                if !(density >= 0.0 && density.is_finite()) {
+                    return Err(Box::new(SceneError(format!(
+                        "a volume's `density` needs to be a number that isn't negative, not {}",
+                        density
+                    ))));
+                }
+                let phase_function = volume_setting.material.resolve(&library)?;
+                world.push(Box::new(ConstantMedium::new(
+                    volume_setting.boundary.to_hit(phase_function.clone())?,
+                    density,
+                    phase_function,
+                )));
+            }
+        }
+        if let Some(grid_settings) = &scene_settings.grids {
+            for grid_setting in grid_settings {
+                world.push(Box::new(grid_setting.to_medium(&library)?));
+            }
+        }
+        for light_setting in scene_settings.lights.iter().flatten() {
+            light_setting.add_to(&mut world, &mut lights)?;
+        }
+        Ok((Bvh::new(world), lights))
+    } else {
+        Ok((Bvh::new(random_scene()), Lights::new()))
+    }
+}
+
+// A mesh given in the preset, checked first since MeshData only takes consistent buffers.
+fn inline_mesh(
+    vertices: &[Point3],
+    indices: &[[usize; 3]],
+    normals: &Option<Vec<Vec3>>,
+    uvs: &Option<Vec<[f64; 2]>>,
+) -> Result<MeshData, Box<dyn Error>> {
+    if let Some(&i) = indices.iter().flatten().find(|&&i| i >= vertices.len()) {
+        return Err(Box::new(SceneError(format!(
+            "mesh index {} is out of range for {} vertices",
+            i,
+            vertices.len()
+        ))));
+    }
+    if normals.as_ref().is_some_and(|n| n.len() != vertices.len()) {
+        return Err(Box::new(SceneError(
+            "a mesh needs either no `normals` or one normal per vertex".to_string(),
+        )));
+    }
+    if uvs.as_ref().is_some_and(|uvs| uvs.len() != vertices.len()) {
+        return Err(Box::new(SceneError(
+            "a mesh needs either no `uvs` or one pair per vertex".to_string(),
+        )));
+    }
+    Ok(MeshData::new(
+        vertices.to_vec(),
+        indices.to_vec(),
+        normals.clone(),
+        uvs.clone(),
+    ))
+}
+
+fn add_mesh(
+    world: &mut World,
+    mesh_setting: &MeshSettings,
+    library: &HashMap<String, Arc<dyn Scatter>>,
+) -> Result<(), Box<dyn Error>> {
+    let material = match &mesh_setting.material {
+        Some(material) => Some(material.resolve(library)?),
+        None => None,
+    };
+
+    if let Some(obj) = &mesh_setting.obj {
+        if mesh_setting.vertices.is_some() || mesh_setting.indices.is_some() {
+            return Err(Box::new(SceneError(format!(
+                "the mesh from {} can't also have inline `vertices` or `indices`",
+                obj
+            ))));
+        }
+
+        let obj_file = load_obj(Path::new(obj))?;
+        let mtl_materials: HashMap<&String, Arc<dyn Scatter>> = obj_file
+            .materials
+            .iter()
+            .map(|(name, mtl)| (name, mtl.to_scatter()))
+            .collect();
+        for group in obj_file.groups {
+            let mat = match (&material, &group.material) {
+                (Some(mat), _) => mat.clone(),
+                (None, Some(name)) => mtl_materials[name].clone(),
+                // Faces before any `usemtl` get a plain grey.
+                (None, None) => Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+            };
+            world.push(Box::new(TriangleMesh::new(Arc::new(group.mesh), mat)));
+        }
+        return Ok(());
+    }
+
+    match (&mesh_setting.vertices, &mesh_setting.indices, material) {
+        (Some(vertices), Some(indices), Some(material)) => {
+            let mesh = Arc::new(inline_mesh(
+                vertices,
+                indices,
+                &mesh_setting.normals,
+                &mesh_setting.uvs,
+            )?);
+            world.push(Box::new(TriangleMesh::new(mesh, material)));
+            Ok(())
+        }
+        _ => Err(Box::new(SceneError(
+            "a mesh needs either an `obj` file, or `vertices`, `indices` and a `material`"
+                .to_string(),
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ray::Ray;
+
+    fn material(json: &str) -> Result<MaterialSettings, serde_json::Error> {
+        match serde_json::from_str::<ObjectMaterial>(json)? {
+            ObjectMaterial::Inline(settings) => Ok(settings),
+            ObjectMaterial::Named(name) => panic!("expected an inline material, not `{}`", name),
+        }
+    }
+
+    #[test]
+    fn tagged_and_legacy_materials_are_accepted() {
+        let tagged = material(r#"{"type": "dielectric", "ir": 1.5}"#).unwrap();
+        assert!(matches!(
+            tagged,
+            MaterialSettings::Dielectric { ir: IorSettings::Constant(ir), .. } if ir == 1.5
+        ));
+
+        let legacy = material(r#"{"metal": {"albedo": {"e": [1, 1, 1]}, "fuzz": 0.2}}"#).unwrap();
+        assert!(matches!(legacy, MaterialSettings::Metal { fuzz, .. } if fuzz == 0.2));
+    }
+
+    #[test]
+    fn ambiguous_materials_are_rejected() {
+        let error = material(
+            r#"{"metal": {"albedo": {"e": [1, 1, 1]}, "fuzz": 0.2}, "dielectric": {"ir": 1.5}}"#,
+        )
+        .err()
+        .unwrap();
+        assert!(error.to_string().contains("ambiguous material"));
+
+        assert!(material(r#"{"type": "dielectric", "ir": 1.5, "fuzz": 0.2}"#).is_err());
+
+        // Tinted glass needs some distance to reach its colour over.
+        for distance in ["0", "-1"] {
+            let glass = material(&format!(
+                r#"{{"type": "dielectric", "ir": 1.5, "color": {{"e": [1, 0.5, 0.5]}}, "distance": {}}}"#,
+                distance
+            ))
+            .unwrap();
+            assert!(glass.to_scatter().is_err());
+        }
+    }
+
+    #[test]
+    fn named_materials_are_shared() {
+        let scene = |material: &str| {
+            let settings: SceneSettings = serde_json::from_str(&format!(
+                r#"{{
+                    "materials": {{"glass": {{"type": "dielectric", "ir": 1.5}}}},
+                    "spheres": [
+                        {{"center": {{"e": [0, 0, 0]}}, "radius": 1, "material": "glass"}},
+                        {{"center": {{"e": [3, 0, 0]}}, "radius": 1, "material": "{}"}}
+                    ]
+                }}"#,
+                material
+            ))
+            .unwrap();
+            construct_scene_from_settings(&Some(settings)).map(|(world, _)| world)
+        };
+
+        let world = scene("glass").unwrap();
+        let material_at = |x: f64| {
+            let r = Ray::new(Point3::new(x, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+            world.hit(&r, 0.001, f64::INFINITY).unwrap().mat
+        };
+        assert!(Arc::ptr_eq(&material_at(0.0), &material_at(3.0)));
+
+        let error = scene("brass").err().unwrap();
+        assert!(error.to_string().contains("unknown material `brass`"));
+    }
+
+    #[test]
+    fn lights_without_area_are_errors() {
+        let add = |json: &str| {
+            let light: LightSettings = serde_json::from_str(json).unwrap();
+            let mut lights = Lights::new();
+            light.add_to(&mut World::new(), &mut lights).map(|_| lights)
+        };
+        for radius in ["0", "-1"] {
+            let sphere = format!(
+                r#"{{"type": "sphere", "center": {{"e": [0, 0, 0]}}, "radius": {}, "emit": {{"e": [1, 1, 1]}}}}"#,
+                radius
+            );
+            assert!(add(&sphere).is_err());
+        }
+
+        // Corners the other way round make the same rectangle.
+        let lights = add(
+            r#"{"type": "rectangle", "min_point": {"e": [1, 1, -2]}, "max_point": {"e": [-1, -1, -2]}, "emit": {"e": [1, 1, 1]}}"#,
+        )
+        .unwrap();
+        let sample = lights.sample_area(Point3::new(0.0, 0.0, 0.0)).unwrap();
+        assert!(sample.pdf.is_finite() && sample.pdf > 0.0);
+
+        // Flat along x or y, or not flat along z.
+        for (min, max) in [
+            ("[1, -1, -2]", "[1, 1, -2]"),
+            ("[-1, 1, -2]", "[1, 1, -2]"),
+            ("[-1, -1, -2]", "[1, 1, -3]"),
+        ] {
+            let rectangle = format!(
+                r#"{{"type": "rectangle", "min_point": {{"e": {}}}, "max_point": {{"e": {}}}, "emit": {{"e": [1, 1, 1]}}}}"#,
+                min, max
+            );
+            assert!(add(&rectangle).is_err());
+        }
+    }
+
+    #[test]
+    fn negative_densities_are_errors() {
+        let build = |json: &str| {
+            let settings: SceneSettings = serde_json::from_str(json).unwrap();
+            construct_scene_from_settings(&Some(settings)).map(|_| ())
+        };
+        let volume = |density: &str| {
+            format!(
+                r#"{{"volumes": [{{
+                    "boundary": {{"type": "sphere", "center": {{"e": [0, 0, 0]}}, "radius": 1}},
+                    "density": {},
+                    "material": {{"type": "isotropic", "albedo": {{"e": [1, 1, 1]}}}}
+                }}]}}"#,
+                density
+            )
+        };
+        assert!(build(&volume("0.5")).is_ok());
+        assert!(build(&volume("-0.5")).is_err());
+
+        let grid = r#"{"grids": [{
+            "path": "smoke.vol",
+            "density": -2,
+            "material": {"type": "isotropic", "albedo": {"e": [1, 1, 1]}}
+        }]}"#;
+        let error = build(grid).err().unwrap();
+        assert!(error.to_string().contains("density"));
+    }
+
+    #[test]
+    fn broken_inline_meshes_are_errors() {
+        let scene = |mesh: &str| {
+            let settings: Option<SceneSettings> = Some(
+                serde_json::from_str(&format!(
+                    r#"{{"meshes": [{{"material": {{"type": "lambertian", "albedo": {{"e": [1, 1, 1]}}}}, {}}}]}}"#,
+                    mesh
+                ))
+                .unwrap(),
+            );
+            construct_scene_from_settings(&settings)
+                .err()
+                .map(|e| e.to_string())
+        };
+        let vertices = r#""vertices": [{"e": [0, 0, 0]}, {"e": [1, 0, 0]}, {"e": [0, 1, 0]}]"#;
+
+        assert!(scene(&format!(r#"{}, "indices": [[0, 1, 2]]"#, vertices)).is_none());
+        assert!(scene(&format!(r#"{}, "indices": [[0, 1, 3]]"#, vertices))
+            .unwrap()
+            .contains("out of range"));
+        assert!(scene(&format!(
+            r#"{}, "indices": [[0, 1, 2]], "normals": [{{"e": [0, 0, 1]}}]"#,
+            vertices
+        ))
+        .unwrap()
+        .contains("normals"));
+    }
+}
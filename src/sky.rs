@@ -0,0 +1,202 @@
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use rand::Rng;
+
+use super::light::{Light, LightSample};
+use super::microfacet::Frame;
+use super::ray::Ray;
+use super::spectrum::xyz_to_linear_srgb;
+use super::vec::{Color, Point3, Vec3};
+
+// The sun's angular radius, in radians.
+const SUN_RADIUS: f64 = 0.00465;
+// The sun's luminance above the atmosphere, in the sky's units (kcd/m^2).
+const SUN_LUMINANCE: f64 = 2.0e6;
+// Wavelengths, in micrometres, at which the sun's colour is worked out for red, green and blue.
+const RGB_WAVELENGTHS: [f64; 3] = [0.680, 0.550, 0.440];
+
+// Perez et al.'s formula for how the sky's brightness varies, with the angle `theta` from the
+// zenith and the angle `gamma` from the sun.
+fn perez(c: &[f64; 5], cos_theta: f64, gamma: f64) -> f64 {
+    (1.0 + c[0] * (c[1] / cos_theta).exp())
+        * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * gamma.cos().powi(2))
+}
+
+// The clear-sky model of Preetham, Shirley and Smits, "A Practical Analytic Model for Daylight"
+// (1999), with a sun disk whose colour comes from the same paper's atmospheric attenuation.
+pub struct Sky {
+    // Towards the sun.
+    sun_direction: Vec3,
+    sun_theta: f64,
+    // Luminance and chromaticity at the zenith, and the Perez coefficients for each.
+    zenith: [f64; 3],
+    coefficients: [[f64; 5]; 3],
+    sun_radiance: Color,
+    intensity: f64,
+}
+
+impl Sky {
+    // `elevation` is the sun's angle above the horizon, and `azimuth` its angle around the y axis
+    // from -z towards +x, both in degrees. `turbidity` is how hazy the air is, from 2 (very clear)
+    // to about 10.
+    pub fn new(elevation: f64, azimuth: f64, turbidity: f64, intensity: f64) -> Sky {
+        let (elevation, azimuth) = (elevation.to_radians(), azimuth.to_radians());
+        let sun_direction = Vec3::new(
+            elevation.cos() * azimuth.sin(),
+            elevation.sin(),
+            -elevation.cos() * azimuth.cos(),
+        );
+        // The model only holds with the sun above the horizon.
+        let theta = (PI / 2.0 - elevation).clamp(0.0, PI / 2.0);
+        let t = turbidity;
+
+        let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta);
+        let luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+        let (t2, t3) = (theta * theta, theta * theta * theta);
+        let x = t * t * (0.00166 * t3 - 0.00375 * t2 + 0.00209 * theta)
+            + t * (-0.02903 * t3 + 0.06377 * t2 - 0.03202 * theta + 0.00394)
+            + (0.11693 * t3 - 0.21196 * t2 + 0.06052 * theta + 0.25886);
+        let y = t * t * (0.00275 * t3 - 0.00610 * t2 + 0.00317 * theta)
+            + t * (-0.04214 * t3 + 0.08970 * t2 - 0.04153 * theta + 0.00516)
+            + (0.15346 * t3 - 0.26756 * t2 + 0.06670 * theta + 0.26688);
+
+        let coefficients = [
+            [
+                0.1787 * t - 1.4630,
+                -0.3554 * t + 0.4275,
+                -0.0227 * t + 5.3251,
+                0.1206 * t - 2.5771,
+                -0.0670 * t + 0.3703,
+            ],
+            [
+                -0.0193 * t - 0.2592,
+                -0.0665 * t + 0.0008,
+                -0.0004 * t + 0.2125,
+                -0.0641 * t - 0.8989,
+                -0.0033 * t + 0.0452,
+            ],
+            [
+                -0.0167 * t - 0.2608,
+                -0.0950 * t + 0.0092,
+                -0.0079 * t + 0.2102,
+                -0.0441 * t - 1.6537,
+                -0.0109 * t + 0.0529,
+            ],
+        ];
+
+        // Sunlight loses blue to Rayleigh scattering and everything to haze, over the length of
+        // air it crosses (Kasten's relative optical mass).
+        let mass = 1.0 / (theta.cos() + 0.15 * (93.885 - theta.to_degrees()).powf(-1.253));
+        let beta = 0.04608 * t - 0.04586;
+        let transmittance = RGB_WAVELENGTHS.map(|lambda: f64| {
+            let rayleigh = (-0.008735 * lambda.powf(-4.08) * mass).exp();
+            let aerosol = (-beta * lambda.powf(-1.3) * mass).exp();
+            rayleigh * aerosol
+        });
+
+        Sky {
+            sun_direction,
+            sun_theta: theta,
+            zenith: [luminance, x, y],
+            coefficients,
+            sun_radiance: SUN_LUMINANCE
+                * Color::new(transmittance[0], transmittance[1], transmittance[2]),
+            intensity,
+        }
+    }
+
+    // The sky without the sun. Below the horizon it carries on as it is at the horizon.
+    fn sky_color(&self, d: Vec3) -> Color {
+        let cos_theta = d.y().max(1.0e-3);
+        let gamma = d.dot(self.sun_direction).clamp(-1.0, 1.0).acos();
+        let [luminance, x, y] = [0, 1, 2].map(|i| {
+            self.zenith[i] * perez(&self.coefficients[i], cos_theta, gamma)
+                / perez(&self.coefficients[i], 1.0, self.sun_theta)
+        });
+        let xyz = Vec3::new(x / y * luminance, luminance, (1.0 - x - y) / y * luminance);
+        let rgb = xyz_to_linear_srgb(xyz);
+        Color::new(rgb.x().max(0.0), rgb.y().max(0.0), rgb.z().max(0.0))
+    }
+
+    fn in_sun(&self, d: Vec3) -> bool {
+        d.y() > 0.0 && d.dot(self.sun_direction) >= SUN_RADIUS.cos()
+    }
+
+    pub fn color(&self, direction: Vec3) -> Color {
+        let d = direction.normalized();
+        let sun = if self.in_sun(d) {
+            self.sun_radiance
+        } else {
+            Color::new(0.0, 0.0, 0.0)
+        };
+        self.intensity * (self.sky_color(d) + sun)
+    }
+}
+
+// The sun disk as a light, sampled uniformly over the cone it fills.
+pub struct SunLight {
+    sky: Arc<Sky>,
+}
+
+impl SunLight {
+    pub fn new(sky: Arc<Sky>) -> SunLight {
+        SunLight { sky }
+    }
+
+    fn solid_angle() -> f64 {
+        // 1 - cos(SUN_RADIUS), without losing precision.
+        4.0 * PI * (SUN_RADIUS / 2.0).sin().powi(2)
+    }
+}
+
+impl Light for SunLight {
+    fn sample(&self, _p: Point3) -> Option<LightSample> {
+        if self.sky.sun_direction.y() <= 0.0 {
+            return None;
+        }
+        let mut rng = rand::thread_rng();
+        let cos_theta = 1.0 - rng.gen::<f64>() * Self::solid_angle() / (2.0 * PI);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * rng.gen::<f64>();
+        let direction = Frame::new(self.sky.sun_direction).to_world(Vec3::new(
+            sin_theta * phi.cos(),
+            sin_theta * phi.sin(),
+            cos_theta,
+        ));
+        Some(LightSample {
+            direction,
+            distance: f64::INFINITY,
+            radiance: self.sky.color(direction),
+            pdf: 1.0 / Self::solid_angle(),
+        })
+    }
+
+    fn pdf(&self, r: &Ray, t: f64) -> f64 {
+        if t.is_finite() || !self.sky.in_sun(r.direction().normalized()) {
+            return 0.0;
+        }
+        1.0 / Self::solid_angle()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skies_are_blue_and_sunsets_are_red() {
+        let noon = Sky::new(60.0, 0.0, 3.0, 1.0);
+        let zenith = noon.color(Vec3::new(0.0, 1.0, 0.0));
+        assert!(zenith.z() > zenith.x());
+        // Near the sun the sky is brighter than away from it.
+        let near = noon.color(Vec3::new(0.0, 0.8, -0.6));
+        let away = noon.color(Vec3::new(0.0, 0.8, 0.6));
+        assert!(near.y() > away.y());
+
+        let sun = |sky: &Sky| sky.sun_radiance / sky.sun_radiance.y();
+        let sunset = Sky::new(2.0, 0.0, 3.0, 1.0);
+        assert!(sun(&sunset).x() > sun(&noon).x());
+        assert!(sun(&sunset).z() < sun(&noon).z());
+    }
+}
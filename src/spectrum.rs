@@ -0,0 +1,158 @@
+use super::vec::{Color, Vec3};
+
+// The range of wavelengths, in nanometres, that spectral mode samples.
+pub const LAMBDA_MIN: f64 = 360.0;
+pub const LAMBDA_MAX: f64 = 830.0;
+
+// The CIE 1931 2-degree colour matching functions, as fitted by Wyman, Sloan and Shirley in
+// "Simple Analytic Approximations to the CIE XYZ Color Matching Functions" (2013).
+pub fn color_matching(lambda: f64) -> Vec3 {
+    let g = |mu: f64, sigma1: f64, sigma2: f64| {
+        let t = (lambda - mu) / if lambda < mu { sigma1 } else { sigma2 };
+        (-0.5 * t * t).exp()
+    };
+    Vec3::new(
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+pub fn xyz_to_linear_srgb(xyz: Vec3) -> Color {
+    Color::new(
+        3.2404542 * xyz.x() - 1.5371385 * xyz.y() - 0.4985314 * xyz.z(),
+        -0.9692660 * xyz.x() + 1.8760108 * xyz.y() + 0.0415560 * xyz.z(),
+        0.0556434 * xyz.x() - 0.2040259 * xyz.y() + 1.0572252 * xyz.z(),
+    )
+}
+
+// Smits' basis spectra, "An RGB to Spectrum Conversion for Reflectances" (1999), in ten equal
+// bins from 380 to 720 nm.
+const SMITS_WHITE: [f64; 10] = [1.0, 1.0, 0.9999, 0.9993, 0.9992, 0.9998, 1.0, 1.0, 1.0, 1.0];
+const SMITS_CYAN: [f64; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0, 0.0, 0.0,
+];
+const SMITS_MAGENTA: [f64; 10] = [
+    1.0, 1.0, 0.9685, 0.2229, 0.0, 0.0458, 0.8369, 1.0, 1.0, 0.9959,
+];
+const SMITS_YELLOW: [f64; 10] = [
+    0.0001, 0.0, 0.1088, 0.6651, 1.0, 1.0, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [f64; 10] = [
+    0.1012, 0.0515, 0.0, 0.0, 0.0, 0.0, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [f64; 10] = [
+    0.0, 0.0, 0.0273, 0.7937, 1.0, 0.9418, 0.1719, 0.0, 0.0, 0.0025,
+];
+const SMITS_BLUE: [f64; 10] = [
+    1.0, 1.0, 0.8916, 0.3323, 0.0, 0.0, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+// The value at `lambda` of a smooth spectrum with the colour `c`, by Smits' method: white for
+// the smallest channel, then cyan, magenta or yellow, then red, green or blue for the rest.
+// Outside 380 to 720 nm the end bins carry on.
+pub fn rgb_to_spectrum(c: Color, lambda: f64) -> f64 {
+    let bin = (((lambda - 380.0) / 34.0).floor().max(0.0) as usize).min(9);
+    let (r, g, b) = (c.x(), c.y(), c.z());
+
+    if r <= g && r <= b {
+        r * SMITS_WHITE[bin]
+            + if g <= b {
+                (g - r) * SMITS_CYAN[bin] + (b - g) * SMITS_BLUE[bin]
+            } else {
+                (b - r) * SMITS_CYAN[bin] + (g - b) * SMITS_GREEN[bin]
+            }
+    } else if g <= r && g <= b {
+        g * SMITS_WHITE[bin]
+            + if r <= b {
+                (r - g) * SMITS_MAGENTA[bin] + (b - r) * SMITS_BLUE[bin]
+            } else {
+                (b - g) * SMITS_MAGENTA[bin] + (r - b) * SMITS_RED[bin]
+            }
+    } else {
+        b * SMITS_WHITE[bin]
+            + if r <= g {
+                (r - b) * SMITS_YELLOW[bin] + (g - r) * SMITS_GREEN[bin]
+            } else {
+                (g - b) * SMITS_YELLOW[bin] + (r - g) * SMITS_RED[bin]
+            }
+    }
+}
+
+// In spectral mode a colour stands for its spectrum at the ray's wavelength, which every channel
+// carries. Without a wavelength colours are left alone.
+pub fn at_wavelength(c: Color, wavelength: Option<f64>) -> Color {
+    match wavelength {
+        Some(lambda) => {
+            let v = rgb_to_spectrum(c, lambda);
+            Color::new(v, v, v)
+        }
+        None => c,
+    }
+}
+
+// Turns radiance carried at single wavelengths back into linear sRGB.
+pub struct SpectrumToRgb {
+    // What a spectrum of constant 1 comes out as, which we divide by so that it comes out white.
+    // (Our sRGB white is D65, not the equal-energy white.)
+    white: Color,
+}
+
+impl SpectrumToRgb {
+    pub fn new() -> SpectrumToRgb {
+        let steps = (LAMBDA_MAX - LAMBDA_MIN) as usize;
+        let mut xyz = Vec3::new(0.0, 0.0, 0.0);
+        for i in 0..steps {
+            xyz += color_matching(LAMBDA_MIN + i as f64 + 0.5);
+        }
+        SpectrumToRgb {
+            white: xyz_to_linear_srgb(xyz),
+        }
+    }
+
+    // The sRGB contribution of `radiance` at wavelength `lambda`, picked uniformly at random.
+    // Averaging these over many wavelengths gives the colour of the whole spectrum.
+    pub fn to_rgb(&self, lambda: f64, radiance: f64) -> Color {
+        let rgb = xyz_to_linear_srgb(radiance * (LAMBDA_MAX - LAMBDA_MIN) * color_matching(lambda));
+        Color::new(
+            rgb.x() / self.white.x(),
+            rgb.y() / self.white.y(),
+            rgb.z() / self.white.z(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Averages the sRGB of the spectrum of `c` over evenly spread wavelengths.
+    fn round_trip(c: Color) -> Color {
+        let converter = SpectrumToRgb::new();
+        let steps = 4700;
+        let mut rgb = Color::new(0.0, 0.0, 0.0);
+        for i in 0..steps {
+            let lambda = LAMBDA_MIN + (i as f64 + 0.5) / steps as f64 * (LAMBDA_MAX - LAMBDA_MIN);
+            rgb += converter.to_rgb(lambda, rgb_to_spectrum(c, lambda));
+        }
+        rgb / steps as f64
+    }
+
+    #[test]
+    fn white_and_grey_survive_the_round_trip() {
+        for grey in [1.0, 0.25] {
+            let rgb = round_trip(Color::new(grey, grey, grey));
+            for i in 0..3 {
+                assert!((rgb[i] - grey).abs() < 0.01 * grey);
+            }
+        }
+    }
+
+    #[test]
+    fn colours_keep_their_hue() {
+        let red = round_trip(Color::new(0.8, 0.1, 0.1));
+        assert!(red.x() > 0.5 && red.y() < 0.3 && red.z() < 0.3);
+        let blue = round_trip(Color::new(0.1, 0.2, 0.9));
+        assert!(blue.z() > 0.6 && blue.x() < 0.3);
+    }
+}
@@ -1,5 +1,6 @@
 use std::sync::Arc;
 
+use super::aabb::Aabb;
 use super::hit::{Hit, HitRecord};
 use super::material::Scatter;
 use super::ray::Ray;
@@ -19,6 +20,43 @@ impl Sphere {
             mat,
         }
     }
+
+    // Longitude and latitude of a point on the unit sphere, both scaled to [0, 1]: u goes around
+    // the y axis starting from -x, and v goes from the bottom (y = -1) to the top.
+    fn uv(p: Point3) -> (f64, f64) {
+        let theta = (-p.y()).clamp(-1.0, 1.0).acos();
+        let phi = (-p.z()).atan2(p.x()) + std::f64::consts::PI;
+        (
+            phi / (2.0 * std::f64::consts::PI),
+            theta / std::f64::consts::PI,
+        )
+    }
+
+    // The derivatives of p with respect to u and v, at the point `local` on the unit sphere.
+    // Following `uv`, that point is (-sin(theta) cos(phi), -cos(theta), sin(theta) sin(phi)) with
+    // phi = 2 pi u and theta = pi v.
+    fn tangents(&self, local: Point3) -> (Vec3, Vec3) {
+        let r = self.radius.abs();
+        let sin_theta = (local.x().powi(2) + local.z().powi(2)).sqrt();
+        if sin_theta < 1.0e-9 {
+            // At the poles u is undefined, so any frame around the normal will do.
+            let (t, b) = local.orthonormal_basis();
+            return (
+                2.0 * std::f64::consts::PI * r * t,
+                std::f64::consts::PI * r * b,
+            );
+        }
+
+        let dpdu = 2.0 * std::f64::consts::PI * r * Vec3::new(local.z(), 0.0, -local.x());
+        let dpdv = std::f64::consts::PI
+            * r
+            * Vec3::new(
+                -local.y() * local.x() / sin_theta,
+                sin_theta,
+                -local.y() * local.z() / sin_theta,
+            );
+        (dpdu, dpdv)
+    }
 }
 
 impl Hit for Sphere {
@@ -42,19 +80,37 @@ impl Hit for Sphere {
         }
 
         let p = r.at(root);
+        // Where p is on the unit sphere. With a negative radius the outward normal points the
+        // other way, but the surface coordinates stay the same.
+        let local = (p - self.center) / self.radius.abs();
+        let (u, v) = Self::uv(local);
+        let (dpdu, dpdv) = self.tangents(local);
         let mut rec = HitRecord {
             t: root,
             p,
             mat: self.mat.clone(),
             normal: Vec3::new(0.0, 0.0, 0.0),
+            shading_normal: Vec3::new(0.0, 0.0, 0.0),
+            u,
+            v,
+            dpdu,
+            dpdv,
             front_face: false,
         };
-
-        let outward_normal = (rec.p - self.center) / self.radius;
+        let outward_normal = (p - self.center) / self.radius;
         rec.set_face_normal(r, outward_normal);
 
         Some(rec)
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        // Hollow glass spheres are made with a negative radius.
+        let r = self.radius.abs();
+        Some(Aabb::new(
+            self.center - Vec3::new(r, r, r),
+            self.center + Vec3::new(r, r, r),
+        ))
+    }
 }
 
 #[cfg(test)]
@@ -75,7 +131,24 @@ mod tests {
             assert_eq!(rec.p[1], -100.5);
             assert_eq!(rec.p[2], -101.0);
         } else {
-            assert!(false);
+            panic!("the ray should have hit the sphere");
+        }
+    }
+
+    #[test]
+    fn sphere_tangents_follow_the_surface_coordinates() {
+        let mat = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let sphere = Sphere::new(Point3::new(1.0, 2.0, 3.0), 2.0, mat);
+        let r = Ray::new(Point3::new(5.0, 4.0, 1.0), Vec3::new(-1.0, -0.5, 0.6));
+        let rec = sphere.hit(&r, 0.001, f64::INFINITY).unwrap();
+
+        // Stepping a little way along dp/du (or dp/dv) should move u (or v) by as much.
+        let h = 1.0e-6;
+        for (tangent, du, dv) in [(rec.dpdu, h, 0.0), (rec.dpdv, 0.0, h)] {
+            assert!(tangent.dot(rec.normal).abs() < 1.0e-9);
+            let (u, v) = Sphere::uv((rec.p + h * tangent - sphere.center) / sphere.radius);
+            assert!((u - rec.u - du).abs() < 1.0e-9);
+            assert!((v - rec.v - dv).abs() < 1.0e-9);
         }
     }
 }
@@ -0,0 +1,307 @@
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde::de::Deserializer;
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::hdr::HdrImage;
+use super::image::load_image;
+use super::perlin::Perlin;
+use super::vec::{Color, Point3};
+
+pub trait Texture: Send + Sync {
+    // The colour at surface coordinates (u, v), which is at point p in the scene.
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color;
+}
+
+pub struct SolidColor {
+    color: Color,
+}
+
+impl SolidColor {
+    pub fn new(color: Color) -> SolidColor {
+        SolidColor { color }
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f64, _v: f64, _p: Point3) -> Color {
+        self.color
+    }
+}
+
+// A 3D checkerboard of cubes `scale` wide, so it doesn't depend on how a surface is parametrised.
+pub struct Checker {
+    odd: Arc<dyn Texture>,
+    even: Arc<dyn Texture>,
+    scale: f64,
+}
+
+impl Checker {
+    pub fn new(odd: Arc<dyn Texture>, even: Arc<dyn Texture>, scale: f64) -> Checker {
+        Checker { odd, even, scale }
+    }
+}
+
+impl Texture for Checker {
+    fn value(&self, u: f64, v: f64, p: Point3) -> Color {
+        let cell = (p.x() / self.scale).floor()
+            + (p.y() / self.scale).floor()
+            + (p.z() / self.scale).floor();
+        if (cell as i64).rem_euclid(2) == 0 {
+            self.even.value(u, v, p)
+        } else {
+            self.odd.value(u, v, p)
+        }
+    }
+}
+
+// What happens to texture coordinates outside [0, 1].
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum WrapMode {
+    // Tile the image.
+    Repeat,
+    // Tile the image, flipping every other copy so the edges meet.
+    Mirror,
+    // Stretch the edge pixels out.
+    Clamp,
+}
+
+impl WrapMode {
+    fn apply(self, i: i64, size: usize) -> usize {
+        let size = size as i64;
+        let i = match self {
+            WrapMode::Repeat => i.rem_euclid(size),
+            WrapMode::Mirror => {
+                let i = i.rem_euclid(2 * size);
+                if i < size {
+                    i
+                } else {
+                    2 * size - 1 - i
+                }
+            }
+            WrapMode::Clamp => i.clamp(0, size - 1),
+        };
+        i as usize
+    }
+}
+
+// An image stretched over the (u, v) unit square, with v = 0 at the bottom row. Lookups blend the
+// four nearest pixels.
+pub struct ImageTexture {
+    image: HdrImage,
+    wrap: WrapMode,
+}
+
+impl ImageTexture {
+    pub fn new(image: HdrImage, wrap: WrapMode) -> ImageTexture {
+        ImageTexture { image, wrap }
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: Point3) -> Color {
+        if self.image.pixels.is_empty() {
+            // Cyan, to stand out as a missing texture.
+            return Color::new(0.0, 1.0, 1.0);
+        }
+
+        // Pixel centres sit at half-integer coordinates.
+        let x = u * self.image.width as f64 - 0.5;
+        let y = (1.0 - v) * self.image.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+
+        let pixel = |dx: i64, dy: i64| {
+            self.image.get(
+                self.wrap.apply(x0 as i64 + dx, self.image.width),
+                self.wrap.apply(y0 as i64 + dy, self.image.height),
+            )
+        };
+        let top = (1.0 - fx) * pixel(0, 0) + fx * pixel(1, 0);
+        let bottom = (1.0 - fx) * pixel(0, 1) + fx * pixel(1, 1);
+        (1.0 - fy) * top + fy * bottom
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum NoiseStyle {
+    // Plain Perlin noise.
+    Smooth,
+    // Several octaves of noise added up.
+    Turbulence,
+    // Stripes along z, bent by turbulence.
+    Marble,
+}
+
+pub struct NoiseTexture {
+    noise: Perlin,
+    color: Color,
+    scale: f64,
+    style: NoiseStyle,
+    depth: u32,
+}
+
+impl NoiseTexture {
+    pub fn new(color: Color, scale: f64, style: NoiseStyle, depth: u32) -> NoiseTexture {
+        NoiseTexture {
+            noise: Perlin::new(),
+            color,
+            scale,
+            style,
+            depth,
+        }
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f64, _v: f64, p: Point3) -> Color {
+        let p = self.scale * p;
+        let brightness = match self.style {
+            NoiseStyle::Smooth => 0.5 * (1.0 + self.noise.noise(p)),
+            NoiseStyle::Turbulence => self.noise.turb(p, self.depth),
+            NoiseStyle::Marble => {
+                0.5 * (1.0 + (p.z() + 10.0 * self.noise.turb(p, self.depth)).sin())
+            }
+        };
+        brightness * self.color
+    }
+}
+
+// A texture in a preset. Anywhere a texture goes, a plain colour like {"e": [1, 0, 0]} works too.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum TextureSettings {
+    Solid {
+        color: Color,
+    },
+    Checker {
+        #[serde(deserialize_with = "boxed_color_or_texture")]
+        odd: Box<TextureSettings>,
+        #[serde(deserialize_with = "boxed_color_or_texture")]
+        even: Box<TextureSettings>,
+        // The width of the squares; 1 by default.
+        scale: Option<f64>,
+    },
+    Image {
+        path: String,
+        // Repeat by default.
+        wrap: Option<WrapMode>,
+    },
+    Noise {
+        // White by default.
+        color: Option<Color>,
+        // How many features fit in a unit of distance; 1 by default.
+        scale: Option<f64>,
+        // Smooth by default.
+        style: Option<NoiseStyle>,
+        // The number of octaves of turbulence; 7 by default.
+        depth: Option<u32>,
+    },
+}
+
+impl TextureSettings {
+    pub fn to_texture(&self) -> io::Result<Arc<dyn Texture>> {
+        self.load(true)
+    }
+
+    // For textures that hold numbers rather than colours, like heights: images are taken as they
+    // are, without decoding sRGB.
+    pub fn to_data_texture(&self) -> io::Result<Arc<dyn Texture>> {
+        self.load(false)
+    }
+
+    fn load(&self, srgb: bool) -> io::Result<Arc<dyn Texture>> {
+        Ok(match self {
+            TextureSettings::Solid { color } => Arc::new(SolidColor::new(*color)),
+            TextureSettings::Checker { odd, even, scale } => Arc::new(Checker::new(
+                odd.load(srgb)?,
+                even.load(srgb)?,
+                scale.unwrap_or(1.0),
+            )),
+            TextureSettings::Image { path, wrap } => Arc::new(ImageTexture::new(
+                load_image(Path::new(path), srgb)?,
+                wrap.unwrap_or(WrapMode::Repeat),
+            )),
+            TextureSettings::Noise {
+                color,
+                scale,
+                style,
+                depth,
+            } => Arc::new(NoiseTexture::new(
+                color.unwrap_or(Color::new(1.0, 1.0, 1.0)),
+                scale.unwrap_or(1.0),
+                style.unwrap_or(NoiseStyle::Smooth),
+                depth.unwrap_or(7),
+            )),
+        })
+    }
+}
+
+// For settings that take a texture: a tagged texture, or a plain colour as a solid texture.
+pub fn color_or_texture<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<TextureSettings, D::Error> {
+    let value = Value::deserialize(deserializer)?;
+    let is_texture = matches!(&value, Value::Object(fields) if fields.contains_key("type"));
+    if is_texture {
+        TextureSettings::deserialize(value).map_err(serde::de::Error::custom)
+    } else {
+        let color = Color::deserialize(value).map_err(serde::de::Error::custom)?;
+        Ok(TextureSettings::Solid { color })
+    }
+}
+
+fn boxed_color_or_texture<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Box<TextureSettings>, D::Error> {
+    color_or_texture(deserializer).map(Box::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn image_lookups_filter_and_wrap() {
+        let image = HdrImage {
+            width: 2,
+            height: 1,
+            pixels: vec![Color::new(0.0, 0.0, 0.0), Color::new(1.0, 1.0, 1.0)],
+        };
+        let p = Point3::new(0.0, 0.0, 0.0);
+
+        let clamped = ImageTexture::new(image, WrapMode::Clamp);
+        assert_eq!(clamped.value(0.5, 0.5, p).x(), 0.5);
+        assert_eq!(clamped.value(0.0, 0.5, p).x(), 0.0);
+
+        // Repeating, the left edge blends with the right-hand pixel of the copy next to it.
+        let repeated = ImageTexture::new(clamped.image, WrapMode::Repeat);
+        assert_eq!(repeated.value(0.0, 0.5, p).x(), 0.5);
+    }
+
+    #[test]
+    fn colors_are_solid_textures() {
+        #[derive(Deserialize)]
+        struct Settings {
+            #[serde(deserialize_with = "color_or_texture")]
+            albedo: TextureSettings,
+        }
+
+        let solid: Settings = serde_json::from_str(r#"{"albedo": {"e": [1, 0, 0]}}"#).unwrap();
+        assert!(matches!(solid.albedo, TextureSettings::Solid { color } if color.x() == 1.0));
+
+        let checker: Settings = serde_json::from_str(
+            r#"{"albedo": {"type": "checker", "odd": {"e": [0, 0, 0]}, "even": {"e": [1, 1, 1]}}}"#,
+        )
+        .unwrap();
+        let texture = checker.albedo.to_texture().unwrap();
+        let white = texture.value(0.0, 0.0, Point3::new(0.5, 0.5, 0.5));
+        let black = texture.value(0.0, 0.0, Point3::new(1.5, 0.5, 0.5));
+        assert_eq!((white.x(), black.x()), (1.0, 0.0));
+    }
+}
@@ -0,0 +1,180 @@
+use std::io;
+
+use serde::Deserialize;
+
+use super::vec::Color;
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Operator {
+    // No compression at all: anything brighter than the white point just clips.
+    Clamp,
+    // Luminance-based Reinhard, extended so that the white point maps to exactly 1.
+    Reinhard,
+    // John Hable's filmic curve from Uncharted 2.
+    Hable,
+    // Krzysztof Narkowicz's fit of the ACES reference rendering transform.
+    Aces,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Transfer {
+    // A plain square root, which is what we've always done.
+    Gamma2,
+    // The piecewise sRGB curve.
+    Srgb,
+}
+
+// How the linear framebuffer is turned into display values for the 8- and 16-bit encoders. Without
+// any settings this does what we always did: clamp, and gamma-correct with gamma = 2.
+#[derive(Deserialize, Default)]
+pub struct ToneMapSettings {
+    operator: Option<Operator>,
+    // In stops: +1 doubles the brightness before tone mapping, -1 halves it.
+    exposure: Option<f64>,
+    // The (exposed) linear value that ends up as pure white. Reinhard without a white point
+    // never quite reaches white.
+    white_point: Option<f64>,
+    transfer: Option<Transfer>,
+}
+
+pub struct ToneMapper {
+    operator: Operator,
+    exposure_scale: f64,
+    white_point: Option<f64>,
+    transfer: Transfer,
+}
+
+// Relative luminance of a linear sRGB colour.
+pub fn luminance(c: Color) -> f64 {
+    0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()
+}
+
+// Hable's filmic curve, before normalising by the white point.
+fn hable_partial(x: f64) -> f64 {
+    const A: f64 = 0.15;
+    const B: f64 = 0.50;
+    const C: f64 = 0.10;
+    const D: f64 = 0.20;
+    const E: f64 = 0.02;
+    const F: f64 = 0.30;
+    ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F
+}
+
+fn aces(x: f64) -> f64 {
+    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+}
+
+fn srgb_encode(v: f64) -> f64 {
+    if v <= 0.0031308 {
+        12.92 * v
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+impl ToneMapper {
+    pub fn new(settings: &ToneMapSettings) -> io::Result<ToneMapper> {
+        // Every operator divides by something that is zero, or infinite, at a white point of 0.
+        if let Some(white_point) = settings.white_point {
+            if !(white_point > 0.0 && white_point.is_finite()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "the `white_point` needs to be a positive number, not {}",
+                        white_point
+                    ),
+                ));
+            }
+        }
+        Ok(ToneMapper {
+            operator: settings.operator.unwrap_or(Operator::Clamp),
+            exposure_scale: 2f64.powf(settings.exposure.unwrap_or(0.0)),
+            white_point: settings.white_point,
+            transfer: settings.transfer.unwrap_or(Transfer::Gamma2),
+        })
+    }
+
+    fn map(&self, c: Color) -> Color {
+        match self.operator {
+            Operator::Clamp => c / self.white_point.unwrap_or(1.0),
+            Operator::Reinhard => {
+                let l = luminance(c);
+                if l <= 0.0 {
+                    return c;
+                }
+                let mapped = match self.white_point {
+                    Some(w) => l * (1.0 + l / (w * w)) / (1.0 + l),
+                    None => l / (1.0 + l),
+                };
+                c * (mapped / l)
+            }
+            Operator::Hable => {
+                // The default white point is Hable's own. (His version also brightens by one stop
+                // first, which here is left to `exposure`.)
+                let white_scale = 1.0 / hable_partial(self.white_point.unwrap_or(11.2));
+                let f = |x: f64| hable_partial(x) * white_scale;
+                Color::new(f(c.x()), f(c.y()), f(c.z()))
+            }
+            Operator::Aces => {
+                let white_scale = self.white_point.map_or(1.0, |w| 1.0 / aces(w));
+                let f = |x: f64| aces(x) * white_scale;
+                Color::new(f(c.x()), f(c.y()), f(c.z()))
+            }
+        }
+    }
+
+    // Maps a linear colour to display values, nominally in [0, 1] (the encoders clamp them).
+    pub fn apply(&self, c: Color) -> [f64; 3] {
+        let mapped = self.map(self.exposure_scale * c);
+        let encode = |v: f64| match self.transfer {
+            Transfer::Gamma2 => v.max(0.0).sqrt(),
+            Transfer::Srgb => srgb_encode(v.max(0.0)),
+        };
+        [encode(mapped.x()), encode(mapped.y()), encode(mapped.z())]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_tone_mapping_is_gamma_two() {
+        let tone_mapper = ToneMapper::new(&ToneMapSettings::default()).unwrap();
+        assert_eq!(
+            tone_mapper.apply(Color::new(0.25, 1.0, 4.0)),
+            [0.5, 1.0, 2.0]
+        );
+    }
+
+    #[test]
+    fn white_point_maps_to_white() {
+        for operator in [Operator::Reinhard, Operator::Hable, Operator::Aces] {
+            let tone_mapper = ToneMapper::new(&ToneMapSettings {
+                operator: Some(operator),
+                exposure: None,
+                white_point: Some(8.0),
+                transfer: Some(Transfer::Srgb),
+            })
+            .unwrap();
+            for v in tone_mapper.apply(Color::new(8.0, 8.0, 8.0)) {
+                assert!((v - 1.0).abs() < 1.0e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn white_points_must_be_positive() {
+        for white_point in [0.0, -1.0, f64::INFINITY, f64::NAN] {
+            let settings = ToneMapSettings {
+                operator: Some(Operator::Hable),
+                exposure: None,
+                white_point: Some(white_point),
+                transfer: None,
+            };
+            assert!(ToneMapper::new(&settings).is_err());
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::Aabb,
+    hit::{Hit, HitRecord},
+    material::Scatter,
+    ray::Ray,
+    vec::{Point3, Vec3},
+};
+
+// Möller–Trumbore ray/triangle intersection. On a hit, returns the ray parameter t together with
+// the barycentric coordinates (b1, b2) of the hit point with respect to p1 and p2.
+// See https://en.wikipedia.org/wiki/M%C3%B6ller%E2%80%93Trumbore_intersection_algorithm
+pub fn intersect(
+    p0: Point3,
+    p1: Point3,
+    p2: Point3,
+    r: &Ray,
+    t_min: f64,
+    t_max: f64,
+) -> Option<(f64, f64, f64)> {
+    const EPS: f64 = 1.0e-12;
+
+    let edge1 = p1 - p0;
+    let edge2 = p2 - p0;
+    let h = r.direction().cross(edge2);
+    let det = edge1.dot(h);
+    // The ray is parallel to the triangle.
+    if det.abs() < EPS {
+        return None;
+    }
+
+    let inv_det = 1.0 / det;
+    let s = r.origin() - p0;
+    let b1 = inv_det * s.dot(h);
+    if !(0.0..=1.0).contains(&b1) {
+        return None;
+    }
+
+    let q = s.cross(edge1);
+    let b2 = inv_det * r.direction().dot(q);
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None;
+    }
+
+    let t = inv_det * edge2.dot(q);
+    if t < t_min || t > t_max {
+        return None;
+    }
+    Some((t, b1, b2))
+}
+
+// The box around a triangle, padded so that axis-aligned triangles don't get a flat box.
+pub fn bounding_box(p0: Point3, p1: Point3, p2: Point3) -> Aabb {
+    Aabb::from_points(p0, p1)
+        .surrounding(&Aabb::from_points(p2, p2))
+        .padded(0.0001)
+}
+
+// A single, flat-shaded triangle. The front face is the one from which the vertices appear in
+// counter-clockwise order.
+pub struct Triangle {
+    p0: Point3,
+    p1: Point3,
+    p2: Point3,
+    mat: Arc<dyn Scatter>,
+}
+
+impl Triangle {
+    pub fn new(p0: Point3, p1: Point3, p2: Point3, mat: Arc<dyn Scatter>) -> Triangle {
+        Triangle { p0, p1, p2, mat }
+    }
+}
+
+impl Hit for Triangle {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let (t, b1, b2) = intersect(self.p0, self.p1, self.p2, r, t_min, t_max)?;
+
+        // Without texture coordinates of its own, p0, p1 and p2 sit at (0, 0), (1, 0) and (0, 1).
+        let mut rec = HitRecord {
+            t,
+            p: r.at(t),
+            mat: self.mat.clone(),
+            normal: Vec3::new(0.0, 0.0, 0.0),
+            shading_normal: Vec3::new(0.0, 0.0, 0.0),
+            u: b1,
+            v: b2,
+            dpdu: self.p1 - self.p0,
+            dpdv: self.p2 - self.p0,
+            front_face: false,
+        };
+        let outward_normal = (self.p1 - self.p0).cross(self.p2 - self.p0).normalized();
+        rec.set_face_normal(r, outward_normal);
+
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(bounding_box(self.p0, self.p1, self.p2))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::vec::Color;
+
+    #[test]
+    fn triangle_hits_are_recorded() {
+        let mat = Arc::new(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        let triangle = Triangle::new(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 0.0, 0.0),
+            Point3::new(0.0, 1.0, 0.0),
+            mat,
+        );
+
+        let inside = Ray::new(Point3::new(0.25, 0.25, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        let rec = triangle.hit(&inside, 0.001, f64::INFINITY).unwrap();
+        assert_eq!(rec.t, 1.0);
+        assert!(rec.front_face);
+        assert_eq!(rec.normal[2], 1.0);
+
+        let outside = Ray::new(Point3::new(0.75, 0.75, 1.0), Vec3::new(0.0, 0.0, -1.0));
+        assert!(triangle.hit(&outside, 0.001, f64::INFINITY).is_none());
+    }
+}
@@ -1,3 +1,4 @@
+use std::f64::consts::PI;
 use std::fmt;
 use std::fmt::Display;
 use std::ops::{
@@ -54,23 +55,6 @@ impl Vec3 {
         self / self.length()
     }
 
-    pub fn format_color(self, samples_per_pixel: u64) -> String {
-        let ir = (256.0
-            * (self[0] / (samples_per_pixel as f64))
-                .sqrt()
-                .clamp(0.0, 0.999)) as u64;
-        let ig = (256.0
-            * (self[1] / (samples_per_pixel as f64))
-                .sqrt()
-                .clamp(0.0, 0.999)) as u64;
-        let ib = (256.0
-            * (self[2] / (samples_per_pixel as f64))
-                .sqrt()
-                .clamp(0.0, 0.999)) as u64;
-
-        format!("{} {} {}", ir, ig, ib)
-    }
-
     pub fn random(r: Range<f64>) -> Vec3 {
         let mut rng = rand::thread_rng();
         Vec3 {
@@ -91,6 +75,15 @@ impl Vec3 {
         }
     }
 
+    // A direction around +z with density cos(theta) / pi, from a point picked uniformly on the
+    // unit disk and lifted up onto the hemisphere (Malley's method).
+    pub fn random_cosine_direction() -> Vec3 {
+        let mut rng = rand::thread_rng();
+        let r = rng.gen::<f64>().sqrt();
+        let phi = 2.0 * PI * rng.gen::<f64>();
+        Vec3::new(r * phi.cos(), r * phi.sin(), (1.0 - r * r).max(0.0).sqrt())
+    }
+
     pub fn random_in_hemisphere(normal: Vec3) -> Vec3 {
         let in_unit_sphere = Self::random_in_unit_sphere();
         if in_unit_sphere.dot(normal) > 0.0 {
@@ -117,6 +110,22 @@ impl Vec3 {
         self[0].abs() < EPS && self[1].abs() < EPS && self[2].abs() < EPS
     }
 
+    // Two unit vectors that make an orthonormal basis together with this (unit) vector, from
+    // "Building an Orthonormal Basis, Revisited" (Duff et al. 2017).
+    pub fn orthonormal_basis(self) -> (Vec3, Vec3) {
+        let sign = 1f64.copysign(self.z());
+        let a = -1.0 / (sign + self.z());
+        let b = self.x() * self.y() * a;
+        (
+            Vec3::new(
+                1.0 + sign * self.x() * self.x() * a,
+                sign * b,
+                -sign * self.x(),
+            ),
+            Vec3::new(b, sign + self.y() * self.y() * a, -self.y()),
+        )
+    }
+
     pub fn reflect(self, n: Vec3) -> Vec3 {
         self - 2.0 * self.dot(n) * n
     }
@@ -153,7 +162,7 @@ impl Add for Vec3 {
 }
 
 impl AddAssign for Vec3 {
-    fn add_assign(&mut self, other: Vec3) -> () {
+    fn add_assign(&mut self, other: Vec3) {
         *self = Vec3 {
             e: [self[0] + other[0], self[1] + other[1], self[2] + other[2]],
         };
@@ -171,7 +180,7 @@ impl Sub for Vec3 {
 }
 
 impl SubAssign for Vec3 {
-    fn sub_assign(&mut self, other: Vec3) -> () {
+    fn sub_assign(&mut self, other: Vec3) {
         *self = Vec3 {
             e: [self[0] - other[0], self[1] - other[1], self[2] - other[2]],
         };
@@ -198,7 +207,7 @@ impl Mul<f64> for Vec3 {
 }
 
 impl MulAssign<f64> for Vec3 {
-    fn mul_assign(&mut self, other: f64) -> () {
+    fn mul_assign(&mut self, other: f64) {
         *self = Vec3 {
             e: [self[0] * other, self[1] * other, self[2] * other],
         };
@@ -226,7 +235,7 @@ impl Div<f64> for Vec3 {
 }
 
 impl DivAssign<f64> for Vec3 {
-    fn div_assign(&mut self, other: f64) -> () {
+    fn div_assign(&mut self, other: f64) {
         *self = Vec3 {
             e: [self[0] / other, self[1] / other, self[2] / other],
         };